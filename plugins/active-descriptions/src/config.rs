@@ -0,0 +1,910 @@
+//! Per-repo configuration.
+//!
+//! Settings are layered; for each key, later sources override earlier ones:
+//!
+//! 1. Built-in defaults.
+//! 2. The `[active-descriptions]` table in jj's own config (user config,
+//!    then repo config, with jj's usual precedence between them).
+//! 3. [`CONFIG_FILE_NAME`] at the workspace root.
+//! 4. `--config-set key=value` arguments (see [`parse_overrides`]).
+//!
+//! Missing sources are skipped. A malformed one is reported under
+//! `ACTIVE_DESCRIPTIONS_DEBUG` and the whole config falls back to defaults,
+//! so config mistakes never block Claude.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context as _, Result, bail};
+use globset::{Glob, GlobMatcher};
+use jj_lib::config::{ConfigGetError, StackedConfig};
+use jj_lib::repo_path::RepoPath;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::StaleReason;
+
+/// File name of the per-repo config, relative to the workspace root.
+pub const CONFIG_FILE_NAME: &str = ".active-descriptions.toml";
+
+/// Default for [`Config::max_candidates`].
+pub const DEFAULT_MAX_CANDIDATES: usize = 300;
+
+/// Default for [`Config::max_message_bytes`].
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 10_000;
+
+/// Default for [`Config::change_id_length`].
+pub const DEFAULT_CHANGE_ID_LENGTH: usize = 12;
+
+/// Default for [`Config::remediation_command`].
+pub const DEFAULT_REMEDIATION_COMMAND: &str = "jj describe -r {change_id}";
+
+/// Table in jj's config holding our settings.
+pub const JJ_CONFIG_NAMESPACE: &str = "active-descriptions";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Revset selecting the changes to check. Defaults to
+    /// [`DEFAULT_REVSET`](crate::DEFAULT_REVSET).
+    pub revset: Option<String>,
+    /// Remote-tracking bookmark (e.g. `"main@origin"`) to check everything
+    /// since, for reviewing before a push. Replaces `revset` with
+    /// `<since_remote>..@ ~ empty()` when the bookmark exists; otherwise
+    /// `revset` applies as usual.
+    pub since_remote: Option<String>,
+    /// Descriptions that are never reported stale, such as a team's `merge`
+    /// or `release` markers.
+    pub always_valid_descriptions: Vec<DescriptionPattern>,
+    /// Revset whose fork point each change is diffed against, instead of
+    /// its immediate parent (e.g. `"trunk()"`).
+    ///
+    /// By default a change is stale when *its own* diff changed. With a
+    /// base, it's stale when the net diff of the stack up to and including
+    /// it changed, so edits lower in the stack also flag changes above them.
+    pub fingerprint_base: Option<String>,
+    /// List every file in a stale change's current diff, rather than only
+    /// the files that changed since it was described.
+    ///
+    /// With [`fingerprint_base`](Self::fingerprint_base) this is the net
+    /// change against the fork point: a file touched by both the change and
+    /// its ancestors is listed once, as it would appear in a diff against
+    /// trunk. Without it, it's the change's own diff from its parent. Only
+    /// the listing changes; detection is unaffected.
+    pub net_changed_files: bool,
+    /// Don't count adding or deleting an empty file (e.g. `__init__.py` or
+    /// `.gitkeep`) as drift. Editing a file to or from empty still counts.
+    pub ignore_empty_files: bool,
+    /// Compare text files by content with trailing whitespace and line
+    /// endings normalized, so a reformatting run isn't drift. Binary and
+    /// conflicted files still compare by tree value.
+    pub whitespace_insensitive: bool,
+    /// Count the lines added and removed in each changed file, reported as
+    /// e.g. `src/foo.rs (+12/-4)`. Off by default: it reads every changed
+    /// file's contents.
+    pub line_counts: bool,
+    /// Don't count drift touching fewer lines than this, in total across
+    /// files, so a typo fix after describing doesn't need a new description.
+    /// Binary and conflicted files always count as drift. `0` (the default)
+    /// counts any change.
+    pub min_changed_lines: usize,
+    /// Cache each commit's diff fingerprint in the state dir, so stop-hook
+    /// retries don't recompute diffs. Entries are keyed by commit id, which
+    /// is content-addressed, and the settings that shape the diff, so they
+    /// never go out of date; ones older than a week are pruned.
+    pub fingerprint_cache: bool,
+    /// Don't flag changes with empty descriptions, e.g. while spiking. Drift
+    /// detection still applies to described changes.
+    pub skip_empty_check: bool,
+    /// Never flag merge commits. Otherwise a merge is checked like any other
+    /// change, by its diff from the auto-merged parent trees: what the merge
+    /// adds on top of a clean merge, such as conflict resolutions.
+    pub skip_merges: bool,
+    /// Only check changes authored by the configured jj `user.email`, so
+    /// changes from bots or teammates in a shared stack aren't flagged.
+    pub only_mine: bool,
+    /// Don't flag an empty description until the change's author timestamp
+    /// is this many seconds old, leaving time to write one. `0` (the
+    /// default) flags immediately.
+    pub empty_grace_period_secs: u64,
+    /// Don't flag any change until its committer timestamp is this many
+    /// seconds old, so the stop hook doesn't block right after an edit that
+    /// is about to be described. `0` (the default) flags immediately.
+    pub grace_seconds: u64,
+    /// Flag described changes touching more than this many files whose
+    /// description is only a subject line. Off when unset.
+    pub require_body_over_files: Option<usize>,
+    /// Flag descriptions shorter than this many characters once trimmed,
+    /// like `wip` or `fix`. `0` (the default) allows any length.
+    pub min_description_chars: usize,
+    /// Ignore case and whitespace when looking for the last describe, so
+    /// reformatting a description doesn't re-anchor drift detection.
+    pub normalize_descriptions: bool,
+    /// Which evolog entry a change's current diff is compared against.
+    pub describe_point: DescribePointStrategy,
+    /// Ignore markdown syntax (headings, list markers, emphasis) when looking
+    /// for the last describe, so e.g. turning a paragraph into bullets
+    /// doesn't re-anchor drift detection.
+    pub ignore_markdown: bool,
+    /// Whether each reason blocks the stop hook.
+    pub severity: Severities,
+    /// Custom message for each reason, in place of the built-in phrasing.
+    pub messages: Messages,
+    /// Include up to this many characters of each stale change's current
+    /// description in the output. Off when unset.
+    pub include_description_chars: Option<usize>,
+    /// Phrases that make a description stale wherever they appear, matched
+    /// case-insensitively (e.g. `"asdf"`, `"fix stuff"`).
+    pub banned_phrases: Vec<String>,
+    /// Placeholder subjects that make a description stale, like `"wip"` or
+    /// `"tmp"`, matched against the whole subject case-insensitively and
+    /// ignoring surrounding whitespace. Entries ending in `!`, like
+    /// `"fixup!"`, match as a prefix.
+    pub placeholder_descriptions: Vec<String>,
+    /// Regex every described change's subject must match, e.g.
+    /// `'^[A-Z]+-\d+: '` for an issue-key prefix. Off when unset.
+    pub require_pattern: Option<SubjectPattern>,
+    /// Flag described changes whose subject isn't a Conventional Commit
+    /// (`type(scope): subject`), separately from content drift.
+    pub require_conventional: bool,
+    /// Flag subject-only descriptions that just name the files they touch,
+    /// like "update main.rs". Off by default; the heuristic errs towards not
+    /// flagging.
+    pub flag_filename_restatements: bool,
+    /// Flag subjects with fewer than this many meaningful words after the
+    /// conventional-commit prefix, so `feat:` or `feat: wip` don't pass.
+    /// Off when unset.
+    pub min_subject_words: Option<usize>,
+    /// Flag descriptions that name only files another child of the same
+    /// parent touches, none of the change's own, as after a bad squash. Off
+    /// by default; a description naming no paths is never flagged.
+    pub flag_mismatched_descriptions: bool,
+    /// Flag descriptions naming a file the change touched when described
+    /// but no longer does, as [`StaleReason::ReferencesMissingFile`] rather
+    /// than plain drift. Off by default.
+    pub flag_missing_references: bool,
+    /// Most candidates checked per run; the rest are skipped with a debug
+    /// warning. Defaults to [`DEFAULT_MAX_CANDIDATES`].
+    pub max_candidates: Option<usize>,
+    /// Most evolog entries walked per change, newest first. Defaults to
+    /// [`MAX_EVOLOG_ENTRIES`](crate::MAX_EVOLOG_ENTRIES).
+    pub max_evolog_entries: Option<usize>,
+    /// Times per session the stop hook blocks before letting Claude stop
+    /// anyway. Defaults to [`MAX_STOP_RETRIES`](crate::MAX_STOP_RETRIES).
+    pub max_stop_retries: Option<u32>,
+    /// Don't list files for undescribed changes; the message just says the
+    /// description is missing. Drift still lists changed files.
+    pub omit_files_for_empty: bool,
+    /// Reuse the `jj root` result for runs from the same directory for a
+    /// minute, saving a subprocess per hook run. Read from the workspace
+    /// root, so it takes effect from the run after it's set.
+    pub cache_workspace_root: bool,
+    /// Skip the check while the working copy has edits not yet snapshot
+    /// into `@`, since verdicts on a diff in flux are noisy. The run that
+    /// notices snapshots them, so a later run checks once edits pause.
+    pub require_clean_working_copy: bool,
+    /// Exit code that blocks in stop mode (1–125), for orchestrators other
+    /// than Claude Code. `ACTIVE_DESCRIPTIONS_STOP_EXIT_CODE` overrides it.
+    pub stop_exit_code: Option<i64>,
+    /// Block in stop mode with Claude Code's JSON decision object
+    /// (`{"decision": "block", "reason": ...}`) on stdout and exit 0,
+    /// instead of the report on stderr and `stop_exit_code`. The retry
+    /// budget applies the same way.
+    pub stop_json: bool,
+    /// Command suggested for each stale change in hook and text reports, with
+    /// `{change_id}` replaced by its short change ID. Defaults to
+    /// [`DEFAULT_REMEDIATION_COMMAND`].
+    pub remediation_command: Option<String>,
+    /// How changed files count, as `[[file_classes]]` entries with a `glob`
+    /// and a [`FileClass`]. The first matching glob wins; unmatched files
+    /// are [`FileClass::Content`]. Globs match the repo-relative path, and
+    /// `*` matches across `/`, so `*.lock` matches lockfiles at any depth.
+    pub file_classes: Vec<FileRule>,
+    /// Globs for files like `CHANGELOG.md` whose edits rarely need a new
+    /// description. Drift touching nothing else is reported at
+    /// [`Severity::Warn`] whatever `severity` says. Globs match as in
+    /// `file_classes`.
+    pub trivial_paths: Vec<FileGlob>,
+    /// Report each stale change's position on the stack from trunk (or
+    /// `fingerprint_base`) up to `@` in structured output, to help describe
+    /// from the bottom up.
+    pub stack_positions: bool,
+    /// Whether keys no setting reads, usually typos, are reported under
+    /// debug or fail the config (which then falls back to defaults).
+    pub unknown_keys: UnknownKeys,
+    /// Most bytes of hook message shown to Claude; longer reports are cut
+    /// at a line boundary behind a count of all stale changes. Defaults to
+    /// [`DEFAULT_MAX_MESSAGE_BYTES`].
+    pub max_message_bytes: Option<usize>,
+    /// Fewest characters of each change ID shown. IDs are lengthened past
+    /// this as needed to tell the candidates apart. Defaults to
+    /// [`DEFAULT_CHANGE_ID_LENGTH`].
+    pub change_id_length: Option<usize>,
+}
+
+impl Config {
+    /// Loads the config for the workspace at `workspace_root`, layering
+    /// [`CONFIG_FILE_NAME`] over the [`JJ_CONFIG_NAMESPACE`] table of
+    /// `jj_config`.
+    pub fn load(workspace_root: &Path, jj_config: &StackedConfig) -> Result<Self> {
+        Self::load_with_overrides(workspace_root, jj_config, &toml::Table::new())
+    }
+
+    /// Like [`load`](Self::load), with `overrides` from [`parse_overrides`]
+    /// layered over everything else.
+    pub fn load_with_overrides(
+        workspace_root: &Path,
+        jj_config: &StackedConfig,
+        overrides: &toml::Table,
+    ) -> Result<Self> {
+        let mut table = match jj_config.get::<toml::Table>(JJ_CONFIG_NAMESPACE) {
+            Ok(table) => table,
+            Err(ConfigGetError::NotFound { .. }) => toml::Table::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("invalid [{JJ_CONFIG_NAMESPACE}] in jj config"));
+            }
+        };
+        let mut unknown: Vec<(String, String)> = unknown_keys(&table)
+            .into_iter()
+            .map(|key| (key, format!("[{JJ_CONFIG_NAMESPACE}] in jj config")))
+            .collect();
+
+        let path = workspace_root.join(CONFIG_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let file: toml::Table = toml::from_str(&text)
+                    .with_context(|| format!("invalid config in {}", path.display()))?;
+                unknown.extend(
+                    unknown_keys(&file)
+                        .into_iter()
+                        .map(|key| (key, path.display().to_string())),
+                );
+                merge_tables(&mut table, file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", path.display()));
+            }
+        }
+
+        merge_tables(&mut table, overrides.clone());
+
+        let config: Self = toml::Value::Table(table).try_into()?;
+        config.report_unknown_keys(&unknown)?;
+        Ok(config)
+    }
+
+    /// Parses config from TOML text.
+    pub fn parse(text: &str) -> Result<Self> {
+        let table: toml::Table = toml::from_str(text)?;
+        let unknown: Vec<(String, String)> = unknown_keys(&table)
+            .into_iter()
+            .map(|key| (key, "config text".to_owned()))
+            .collect();
+        let config: Self = toml::Value::Table(table).try_into()?;
+        config.report_unknown_keys(&unknown)?;
+        Ok(config)
+    }
+
+    /// Warns under debug about each `(key, location)` in `unknown`, or fails
+    /// on the first with `unknown_keys = "error"`.
+    fn report_unknown_keys(&self, unknown: &[(String, String)]) -> Result<()> {
+        for (key, location) in unknown {
+            let message = format!("unknown config key `{key}` in {location}");
+            match self.unknown_keys {
+                UnknownKeys::Warn => tracing::debug!("{message}"),
+                UnknownKeys::Error => bail!(message),
+            }
+        }
+        Ok(())
+    }
+
+    /// The candidate revset.
+    pub fn revset(&self) -> &str {
+        self.revset.as_deref().unwrap_or(crate::DEFAULT_REVSET)
+    }
+
+    /// The candidate cap.
+    pub fn max_candidates(&self) -> usize {
+        self.max_candidates.unwrap_or(DEFAULT_MAX_CANDIDATES)
+    }
+
+    /// The per-change evolog walk cap.
+    pub fn max_evolog_entries(&self) -> usize {
+        self.max_evolog_entries.unwrap_or(crate::MAX_EVOLOG_ENTRIES)
+    }
+
+    /// The stop hook's blocking budget per session.
+    pub fn max_stop_retries(&self) -> u32 {
+        self.max_stop_retries.unwrap_or(crate::MAX_STOP_RETRIES)
+    }
+
+    /// The shortest change ID prefix shown.
+    pub fn change_id_length(&self) -> usize {
+        self.change_id_length.unwrap_or(DEFAULT_CHANGE_ID_LENGTH)
+    }
+
+    /// The hook message size cap.
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes.unwrap_or(DEFAULT_MAX_MESSAGE_BYTES)
+    }
+
+    /// The per-change remediation command template.
+    pub fn remediation_command(&self) -> &str {
+        self.remediation_command
+            .as_deref()
+            .unwrap_or(DEFAULT_REMEDIATION_COMMAND)
+    }
+
+    /// The first of `placeholder_descriptions` that `description`'s subject
+    /// is, if any.
+    pub fn placeholder_in(&self, description: &str) -> Option<&str> {
+        let subject = description
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        self.placeholder_descriptions
+            .iter()
+            .find(|placeholder| {
+                let placeholder = placeholder.trim().to_lowercase();
+                if placeholder.ends_with('!') {
+                    subject.starts_with(&placeholder)
+                } else {
+                    subject == placeholder
+                }
+            })
+            .map(String::as_str)
+    }
+
+    /// The first of `banned_phrases` that `description` contains, if any.
+    pub fn banned_phrase_in(&self, description: &str) -> Option<&str> {
+        let description = description.to_lowercase();
+        self.banned_phrases
+            .iter()
+            .find(|phrase| description.contains(&phrase.to_lowercase()))
+            .map(String::as_str)
+    }
+
+    /// `require_pattern`, if `description`'s subject doesn't match it.
+    pub fn subject_pattern_mismatch(&self, description: &str) -> Option<&str> {
+        let pattern = self.require_pattern.as_ref()?;
+        let subject = description.lines().next().unwrap_or_default();
+        (!pattern.0.is_match(subject)).then(|| pattern.0.as_str())
+    }
+
+    /// Whether `require_conventional` is set and `description`'s subject
+    /// isn't a Conventional Commit: `type(scope)!: subject`, with the scope
+    /// and `!` optional and one of the describe skill's types.
+    pub fn breaks_conventional_format(&self, description: &str) -> bool {
+        let subject = description.lines().next().unwrap_or_default();
+        let well_formed = crate::strip_conventional_prefix(subject)
+            .and_then(|rest| rest.strip_prefix(' '))
+            .is_some_and(|text| text.starts_with(|c: char| !c.is_whitespace()));
+        self.require_conventional && !well_formed
+    }
+
+    /// The [`FileClass`] of `path`, per `file_classes`.
+    pub fn classify(&self, path: &RepoPath) -> FileClass {
+        let path = path.as_internal_file_string();
+        self.file_classes
+            .iter()
+            .find(|rule| rule.glob.0.is_match(path))
+            .map_or(FileClass::Content, |rule| rule.class)
+    }
+
+    /// Whether `path` matches one of `trivial_paths`.
+    pub fn is_trivial(&self, path: &RepoPath) -> bool {
+        let path = path.as_internal_file_string();
+        self.trivial_paths.iter().any(|glob| glob.0.is_match(path))
+    }
+
+    /// The settings a classified fingerprint depends on besides the commit:
+    /// `file_classes`, in order, and `ignore_empty_files`. Keys cached
+    /// fingerprints.
+    pub fn fingerprint_inputs(&self) -> String {
+        let mut inputs = format!("ignore_empty_files={}", self.ignore_empty_files);
+        for rule in &self.file_classes {
+            inputs.push_str(&format!("\n{:?}={}", rule.class, rule.glob.0.glob()));
+        }
+        inputs
+    }
+
+    /// Whether any of `file_classes` assigns `class`.
+    pub fn has_class(&self, class: FileClass) -> bool {
+        self.file_classes.iter().any(|rule| rule.class == class)
+    }
+
+    /// Whether `description` matches one of `always_valid_descriptions`.
+    pub fn is_always_valid(&self, description: &str) -> bool {
+        self.always_valid_descriptions
+            .iter()
+            .any(|pattern| pattern.matches(description))
+    }
+}
+
+/// How seriously the stop hook takes a [`StaleReason`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Reported, but the stop hook doesn't block on it.
+    Warn,
+    /// The stop hook blocks until it's fixed.
+    #[default]
+    Block,
+}
+
+/// Parses `--config-set key=value` arguments into a table for
+/// [`Config::load_with_overrides`]. Values are TOML, or else taken as a bare
+/// string, so `revset=main..@` works unquoted; dotted keys reach into
+/// tables. Unlike in config files, unknown keys and mistyped values are
+/// errors: they were typed in for this run.
+pub fn parse_overrides(args: &[String]) -> Result<toml::Table> {
+    let mut overrides = toml::Table::new();
+    for arg in args {
+        let (key, value) = arg
+            .split_once('=')
+            .with_context(|| format!("--config-set expects key=value, got {arg}"))?;
+        let key = key.trim();
+        let table: toml::Table = toml::from_str(&format!("{key} = {value}"))
+            .or_else(|_| toml::from_str(&format!("{key} = {}", toml::Value::from(value))))
+            .with_context(|| format!("invalid --config-set {arg}"))?;
+        if let Some(unknown) = unknown_keys(&table).first() {
+            bail!("unknown config key `{unknown}` in --config-set");
+        }
+        let _: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .with_context(|| format!("invalid --config-set {arg}"))?;
+        merge_tables(&mut overrides, table);
+    }
+    Ok(overrides)
+}
+
+/// Layers `overlay` over `base` key by key, descending into tables present
+/// on both sides so a layer setting `severity.a` keeps another's
+/// `severity.b`.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        if let toml::Value::Table(overlay) = value {
+            if let Some(toml::Value::Table(nested)) = base.get_mut(&key) {
+                merge_tables(nested, overlay);
+                continue;
+            }
+            base.insert(key, toml::Value::Table(overlay));
+        } else {
+            base.insert(key, value);
+        }
+    }
+}
+
+/// Dotted paths of the keys in `table` that no setting reads, including
+/// those nested in `[severity]` and `[messages]`. `[[file_classes]]`
+/// entries reject theirs outright instead.
+fn unknown_keys(table: &toml::Table) -> Vec<String> {
+    let mut unknown = Vec::new();
+    // Deserialization errors surface when the merged table is loaded.
+    let _: Result<Config, _> =
+        serde_ignored::deserialize(toml::Value::Table(table.clone()), |path| {
+            unknown.push(path.to_string());
+        });
+    unknown
+}
+
+/// What [`Config::unknown_keys`] does with a key no setting reads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownKeys {
+    /// Reported under `ACTIVE_DESCRIPTIONS_DEBUG`, then ignored.
+    #[default]
+    Warn,
+    /// Fails loading, like any other malformed config.
+    Error,
+}
+
+/// How [`Config::describe_point`] picks the evolog entry a description was
+/// written against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DescribePointStrategy {
+    /// The entry where the description last changed, falling back to the
+    /// oldest entry with low confidence if it never did.
+    #[default]
+    LastDescriptionChange,
+    /// Always the oldest entry, i.e. creation, for repos whose evolog is
+    /// too lossy to find describes in.
+    FirstEntry,
+}
+
+/// Per-reason [`Severity`], from the `[severity]` table. Every reason blocks
+/// unless configured otherwise.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Severities {
+    pub empty_description: Severity,
+    pub content_changed: Severity,
+    pub missing_body: Severity,
+    pub banned_phrase: Severity,
+    pub restates_filename: Severity,
+    pub subject_too_thin: Severity,
+    pub description_mismatches_diff: Severity,
+    pub pattern_mismatch: Severity,
+    pub references_missing_file: Severity,
+    pub non_conventional_format: Severity,
+    pub description_too_short: Severity,
+    pub placeholder_description: Severity,
+}
+
+impl Severities {
+    pub fn of(&self, reason: StaleReason) -> Severity {
+        match reason {
+            StaleReason::EmptyDescription => self.empty_description,
+            StaleReason::ContentChanged => self.content_changed,
+            StaleReason::MissingBody => self.missing_body,
+            StaleReason::BannedPhrase => self.banned_phrase,
+            StaleReason::RestatesFilename => self.restates_filename,
+            StaleReason::SubjectTooThin => self.subject_too_thin,
+            StaleReason::DescriptionMismatchesDiff => self.description_mismatches_diff,
+            StaleReason::PatternMismatch => self.pattern_mismatch,
+            StaleReason::ReferencesMissingFile => self.references_missing_file,
+            StaleReason::NonConventionalFormat => self.non_conventional_format,
+            StaleReason::DescriptionTooShort => self.description_too_short,
+            StaleReason::PlaceholderDescription => self.placeholder_description,
+        }
+    }
+}
+
+/// Per-reason message templates, from the `[messages]` table, replacing the
+/// built-in headline for that reason. `{change}` is replaced by the short
+/// change ID, `{files}` by the changed files joined with `, ` and `{count}`
+/// by how many there are. The changed files and current description lines
+/// still follow.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Messages {
+    pub empty_description: Option<String>,
+    pub content_changed: Option<String>,
+    pub missing_body: Option<String>,
+    pub banned_phrase: Option<String>,
+    pub restates_filename: Option<String>,
+    pub subject_too_thin: Option<String>,
+    pub description_mismatches_diff: Option<String>,
+    pub pattern_mismatch: Option<String>,
+    pub references_missing_file: Option<String>,
+    pub non_conventional_format: Option<String>,
+    pub description_too_short: Option<String>,
+    pub placeholder_description: Option<String>,
+}
+
+impl Messages {
+    /// No templates: every reason uses the built-in phrasing.
+    pub const BUILT_IN: Self = Self {
+        empty_description: None,
+        content_changed: None,
+        missing_body: None,
+        banned_phrase: None,
+        restates_filename: None,
+        subject_too_thin: None,
+        description_mismatches_diff: None,
+        pattern_mismatch: None,
+        references_missing_file: None,
+        non_conventional_format: None,
+        description_too_short: None,
+        placeholder_description: None,
+    };
+
+    pub fn template(&self, reason: StaleReason) -> Option<&str> {
+        match reason {
+            StaleReason::EmptyDescription => self.empty_description.as_deref(),
+            StaleReason::ContentChanged => self.content_changed.as_deref(),
+            StaleReason::MissingBody => self.missing_body.as_deref(),
+            StaleReason::BannedPhrase => self.banned_phrase.as_deref(),
+            StaleReason::RestatesFilename => self.restates_filename.as_deref(),
+            StaleReason::SubjectTooThin => self.subject_too_thin.as_deref(),
+            StaleReason::DescriptionMismatchesDiff => self.description_mismatches_diff.as_deref(),
+            StaleReason::PatternMismatch => self.pattern_mismatch.as_deref(),
+            StaleReason::ReferencesMissingFile => self.references_missing_file.as_deref(),
+            StaleReason::NonConventionalFormat => self.non_conventional_format.as_deref(),
+            StaleReason::DescriptionTooShort => self.description_too_short.as_deref(),
+            StaleReason::PlaceholderDescription => self.placeholder_description.as_deref(),
+        }
+    }
+}
+
+/// How a changed file counts towards staleness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileClass {
+    /// Participates in drift detection.
+    #[default]
+    Content,
+    /// Left out of every diff, e.g. lockfiles or generated code.
+    Ignore,
+    /// Like `content`, but a change touching it can't be exempted:
+    /// `always_valid_descriptions`, `skip_empty_check` and
+    /// `empty_grace_period_secs` don't apply. Any drift in it is stale,
+    /// whatever `min_changed_lines` and `whitespace_insensitive` say.
+    Sensitive,
+}
+
+/// One `[[file_classes]]` entry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+    pub glob: FileGlob,
+    pub class: FileClass,
+}
+
+/// A glob from config, compiled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct FileGlob(GlobMatcher);
+
+impl TryFrom<String> for FileGlob {
+    type Error = globset::Error;
+
+    fn try_from(glob: String) -> Result<Self, Self::Error> {
+        Ok(Self(Glob::new(&glob)?.compile_matcher()))
+    }
+}
+
+/// A regex from config for [`Config::require_pattern`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SubjectPattern(Regex);
+
+impl TryFrom<String> for SubjectPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Ok(Self(Regex::new(&pattern)?))
+    }
+}
+
+/// A description matcher from config.
+///
+/// Plain strings match the whole description exactly (ignoring surrounding
+/// whitespace); strings prefixed with `regex:` match as a regular expression
+/// anywhere in the description, so anchor with `^`/`$` as needed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DescriptionPattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl DescriptionPattern {
+    pub fn matches(&self, description: &str) -> bool {
+        match self {
+            Self::Exact(text) => description.trim() == text.trim(),
+            Self::Regex(regex) => regex.is_match(description),
+        }
+    }
+}
+
+impl TryFrom<String> for DescriptionPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        match pattern.strip_prefix("regex:") {
+            Some(regex) => Ok(Self::Regex(Regex::new(regex)?)),
+            None => Ok(Self::Exact(pattern)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use jj_lib::config::{ConfigLayer, ConfigSource};
+
+    use super::*;
+
+    fn jj_config(text: &str) -> StackedConfig {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(ConfigLayer::parse(ConfigSource::User, text).expect("parse jj config"));
+        config
+    }
+
+    #[test]
+    fn missing_file_is_default() {
+        let dir = testutils::new_temp_dir();
+        let config = Config::load(dir.path(), &StackedConfig::with_defaults()).expect("load");
+        assert!(config.always_valid_descriptions.is_empty());
+        assert_eq!(config.revset(), crate::DEFAULT_REVSET);
+    }
+
+    #[test]
+    fn reads_jj_config_namespace() {
+        let dir = testutils::new_temp_dir();
+        let jj = jj_config("[active-descriptions]\nrevset = 'mine() ~ empty()'\n");
+        let config = Config::load(dir.path(), &jj).expect("load");
+        assert_eq!(config.revset(), "mine() ~ empty()");
+    }
+
+    #[test]
+    fn file_overrides_jj_config_per_key() {
+        let dir = testutils::new_temp_dir();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "revset = 'trunk()..@'\n")
+            .expect("write config");
+        let jj = jj_config(
+            "[active-descriptions]\n\
+             revset = 'mine()'\n\
+             always_valid_descriptions = ['release']\n",
+        );
+        let config = Config::load(dir.path(), &jj).expect("load");
+        assert_eq!(config.revset(), "trunk()..@");
+        assert!(config.is_always_valid("release"));
+    }
+
+    #[test]
+    fn always_valid_patterns() {
+        let config = Config::parse(r#"always_valid_descriptions = ["release", "regex:^merge\\b"]"#)
+            .expect("parse");
+        assert!(config.is_always_valid("release\n"));
+        assert!(!config.is_always_valid("release notes\n"));
+        assert!(config.is_always_valid("merge main into feature\n"));
+        assert!(!config.is_always_valid("feat: merge helper\n"));
+    }
+
+    #[test]
+    fn fingerprint_base_defaults_to_parent() {
+        assert_eq!(Config::default().fingerprint_base, None);
+        let config = Config::parse(r#"fingerprint_base = "trunk()""#).expect("parse");
+        assert_eq!(config.fingerprint_base.as_deref(), Some("trunk()"));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(Config::parse(r#"always_valid_descriptions = ["regex:("]"#).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_location() {
+        let dir = testutils::new_temp_dir();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        fs::write(&path, "exlude = ['vendor']\n[severity]\n").expect("write config");
+        let table: toml::Table = toml::from_str(
+            "exlude = ['vendor']\n[severity]\nempty_descripton = 'warn'\n[messages]\nbaned_phrase = 'x'",
+        )
+        .expect("parse");
+        assert_eq!(
+            unknown_keys(&table),
+            [
+                "exlude",
+                "messages.baned_phrase",
+                "severity.empty_descripton"
+            ]
+        );
+        // Warnings don't stop the rest of the config loading.
+        assert!(Config::load(dir.path(), &StackedConfig::with_defaults()).is_ok());
+
+        fs::write(&path, "unknown_keys = 'error'\nexlude = ['vendor']\n").expect("write config");
+        let err = Config::load(dir.path(), &StackedConfig::with_defaults())
+            .expect_err("unknown key rejected");
+        assert_eq!(
+            err.to_string(),
+            format!("unknown config key `exlude` in {}", path.display())
+        );
+    }
+
+    #[test]
+    fn config_set_overrides_every_source() {
+        let dir = testutils::new_temp_dir();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "revset = 'trunk()..@'\n")
+            .expect("write config");
+        let jj = jj_config("[active-descriptions]\nrevset = 'mine()'\n");
+        let overrides =
+            parse_overrides(&["revset=main..@".to_owned(), "max_candidates = 5".to_owned()])
+                .expect("parse overrides");
+        let config = Config::load_with_overrides(dir.path(), &jj, &overrides).expect("load");
+        assert_eq!(config.revset(), "main..@");
+        assert_eq!(config.max_candidates(), 5);
+
+        let err = parse_overrides(&["exclude='*.lock'".to_owned()]).expect_err("unknown key");
+        assert_eq!(
+            err.to_string(),
+            "unknown config key `exclude` in --config-set"
+        );
+        let err = parse_overrides(&["max_candidates=many".to_owned()]).expect_err("not a number");
+        assert_eq!(err.to_string(), "invalid --config-set max_candidates=many");
+    }
+
+    #[test]
+    fn partial_file_keeps_other_defaults() {
+        let dir = testutils::new_temp_dir();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "max_stop_retries = 1\n")
+            .expect("write config");
+        let config = Config::load(dir.path(), &StackedConfig::with_defaults()).expect("load");
+        assert_eq!(config.max_stop_retries(), 1);
+        assert_eq!(config.max_evolog_entries(), crate::MAX_EVOLOG_ENTRIES);
+        assert_eq!(config.revset(), crate::DEFAULT_REVSET);
+    }
+
+    #[test]
+    fn malformed_file_is_an_error() {
+        let dir = testutils::new_temp_dir();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            "max_evolog_entries = [\n",
+        )
+        .expect("write config");
+        assert!(Config::load(dir.path(), &StackedConfig::with_defaults()).is_err());
+    }
+
+    #[test]
+    fn conventional_subjects() {
+        let config = Config::parse("require_conventional = true").expect("parse config");
+        for valid in [
+            "feat: add retries",
+            "fix(parser): handle empty input",
+            "refactor(hooks)!: drop the legacy flag\n\nBody.",
+        ] {
+            assert!(!config.breaks_conventional_format(valid), "{valid}");
+        }
+        for invalid in [
+            "Add retries",
+            "feat:add retries",
+            "feat(): add retries",
+            "feat: ",
+            "wip feat: add retries",
+            "feature: add retries",
+        ] {
+            assert!(config.breaks_conventional_format(invalid), "{invalid}");
+        }
+        assert!(!Config::default().breaks_conventional_format("Add retries"));
+    }
+
+    #[test]
+    fn placeholder_subjects() {
+        let config = Config::parse(r#"placeholder_descriptions = ["wip", " TODO ", "fixup!"]"#)
+            .expect("parse config");
+        assert_eq!(config.placeholder_in("wip"), Some("wip"));
+        assert_eq!(
+            config.placeholder_in("  WIP  \n\nStill going."),
+            Some("wip")
+        );
+        assert_eq!(config.placeholder_in("todo"), Some(" TODO "));
+        assert_eq!(
+            config.placeholder_in("fixup! feat: add retries"),
+            Some("fixup!")
+        );
+        assert_eq!(
+            config.placeholder_in("wip: add retries to the parser"),
+            None
+        );
+        assert_eq!(config.placeholder_in("feat: add retries"), None);
+    }
+
+    #[test]
+    fn nested_tables_merge_across_layers() {
+        let dir = testutils::new_temp_dir();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            "[severity]\nempty_description = 'warn'\n",
+        )
+        .expect("write config");
+        let jj = jj_config("[active-descriptions.messages]\nbanned_phrase = 'no'\n");
+        let overrides = parse_overrides(&[
+            "severity.content_changed=warn".to_owned(),
+            "messages.missing_body='body {change}'".to_owned(),
+            "messages.subject_too_thin='thin {change}'".to_owned(),
+        ])
+        .expect("parse overrides");
+        let config = Config::load_with_overrides(dir.path(), &jj, &overrides).expect("load");
+        assert_eq!(config.severity.empty_description, Severity::Warn);
+        assert_eq!(config.severity.content_changed, Severity::Warn);
+        assert_eq!(config.severity.missing_body, Severity::Block);
+        assert_eq!(config.messages.banned_phrase.as_deref(), Some("no"));
+        assert_eq!(
+            config.messages.missing_body.as_deref(),
+            Some("body {change}")
+        );
+        assert_eq!(
+            config.messages.subject_too_thin.as_deref(),
+            Some("thin {change}")
+        );
+    }
+}
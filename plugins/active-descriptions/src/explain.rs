@@ -0,0 +1,346 @@
+//! `--explain`: how the verdict for each candidate was reached, for
+//! investigating reported false positives.
+//!
+//! The human format is for reading; `--format json` gives a stable structure
+//! for snapshot tests. Descriptions are hashed in JSON so snapshots don't
+//! leak commit messages.
+//!
+//! `--print-diff` narrows that to the fingerprint comparison: how each
+//! changed file differs between the describe point and now.
+
+use std::fmt;
+
+use anyhow::Result;
+use jj_lib::backend::CommitId;
+use jj_lib::commit::Commit;
+use jj_lib::content_hash::blake2b_hash;
+use jj_lib::hex_util::encode_hex;
+use jj_lib::object_id::ObjectId as _;
+use jj_lib::repo::ReadonlyRepo;
+use serde::Serialize;
+
+use crate::report::OutputFormat;
+use crate::{
+    CheckOptions, Confidence, StaleReason, check_staleness, classified_fingerprint, describe_point,
+    drifted_paths, evolog_to_describe_point,
+};
+
+/// Hex digits of each description hash kept in JSON output.
+const DESCRIPTION_HASH_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    pub change_id: String,
+    pub commit_id: String,
+    /// The verdict [`check_staleness`] reached, or `None` if not stale.
+    pub reason: Option<StaleReason>,
+    /// Evolog entries inspected, oldest first.
+    pub evolog: Vec<EvologEntry>,
+    /// Index into `evolog` of the entry the current diff is compared
+    /// against, or `None` with fewer than two entries.
+    pub describe_index: Option<usize>,
+    /// `low` when no describe was found and `describe_index` fell back to
+    /// the oldest entry.
+    pub confidence: Option<Confidence>,
+    /// Paths whose fingerprint differs between the describe point and now.
+    pub changed_files: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvologEntry {
+    pub commit_id: String,
+    #[serde(skip)]
+    pub subject: String,
+    pub description_hash: String,
+}
+
+impl From<&Commit> for EvologEntry {
+    fn from(commit: &Commit) -> Self {
+        let hash = encode_hex(&blake2b_hash(commit.description()));
+        Self {
+            commit_id: commit.id().hex(),
+            subject: commit.description().lines().next().unwrap_or("").to_owned(),
+            description_hash: hash[..DESCRIPTION_HASH_LEN].to_owned(),
+        }
+    }
+}
+
+/// Explains the verdict for `commit_id`.
+pub fn explain(
+    repo: &ReadonlyRepo,
+    commit_id: &CommitId,
+    options: &CheckOptions,
+) -> Result<Explanation> {
+    let commit = repo.store().get_commit(commit_id)?;
+    let reason = check_staleness(repo, commit_id, options)?
+        .into_stale()
+        .map(|info| info.reason);
+    let entries = evolog_to_describe_point(repo, commit_id, &options.config)?;
+
+    let (describe_index, confidence) = if entries.len() < 2 {
+        (None, None)
+    } else {
+        let (index, confidence) = describe_point(&entries, &options.config);
+        (Some(index), Some(confidence))
+    };
+
+    let changed_files = match describe_index {
+        Some(index) => {
+            let described =
+                classified_fingerprint(repo, &entries[index], &options.base, &options.config)?;
+            let current = classified_fingerprint(repo, &commit, &options.base, &options.config)?;
+            drifted_paths(repo, &described, &current, &options.config)?
+                .iter()
+                .map(|path| path.as_internal_file_string().to_owned())
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Explanation {
+        change_id: commit.change_id().to_string(),
+        commit_id: commit.id().hex(),
+        reason,
+        evolog: entries.iter().map(EvologEntry::from).collect(),
+        describe_index,
+        confidence,
+        changed_files,
+    })
+}
+
+/// How a path differs between the describe-point fingerprint and the
+/// current one, for `--print-diff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintChange {
+    /// In the current diff only: the change touches it now but didn't when
+    /// described.
+    Added,
+    /// In the described diff only: the change no longer touches it.
+    Removed,
+    /// In both, with different contents.
+    Modified,
+}
+
+impl fmt::Display for FingerprintChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Modified => "modified",
+        })
+    }
+}
+
+/// Each path [`drifted_paths`] reports for `commit_id` and how
+/// it changed, comparing the same fingerprints [`check_staleness`] does.
+/// Empty with fewer than two evolog entries.
+pub fn fingerprint_changes(
+    repo: &ReadonlyRepo,
+    commit_id: &CommitId,
+    options: &CheckOptions,
+) -> Result<Vec<(String, FingerprintChange)>> {
+    let commit = repo.store().get_commit(commit_id)?;
+    let entries = evolog_to_describe_point(repo, commit_id, &options.config)?;
+    if entries.len() < 2 {
+        return Ok(Vec::new());
+    }
+    let (index, _) = describe_point(&entries, &options.config);
+    let described = classified_fingerprint(repo, &entries[index], &options.base, &options.config)?;
+    let current = classified_fingerprint(repo, &commit, &options.base, &options.config)?;
+    Ok(drifted_paths(repo, &described, &current, &options.config)?
+        .into_iter()
+        .map(|path| {
+            let change = match (described.contains_key(&path), current.contains_key(&path)) {
+                (false, _) => FingerprintChange::Added,
+                (_, false) => FingerprintChange::Removed,
+                (true, true) => FingerprintChange::Modified,
+            };
+            (path.as_internal_file_string().to_owned(), change)
+        })
+        .collect())
+}
+
+/// Renders `--print-diff` output for one change.
+pub fn render_fingerprint_changes(
+    change_id: &str,
+    changes: &[(String, FingerprintChange)],
+) -> String {
+    let mut out = format!("change {change_id}\n");
+    if changes.is_empty() {
+        out.push_str("  fingerprints match\n");
+    }
+    for (path, change) in changes {
+        out.push_str(&format!("  {change:<8} {path}\n"));
+    }
+    out
+}
+
+/// Renders explanations as JSON for `--format json`, otherwise for humans.
+pub fn render(format: OutputFormat, explanations: &[Explanation]) -> String {
+    match format {
+        OutputFormat::Json => {
+            let json = serde_json::json!({ "explanations": explanations });
+            format!("{json}\n")
+        }
+        OutputFormat::Ndjson => explanations
+            .iter()
+            .map(|explanation| format!("{}\n", serde_json::json!(explanation)))
+            .collect(),
+        OutputFormat::Hook
+        | OutputFormat::Text
+        | OutputFormat::GithubAnnotations
+        | OutputFormat::Junit
+        | OutputFormat::PlainFiles
+        | OutputFormat::Summary
+        | OutputFormat::Toml
+        | OutputFormat::Count => explanations.iter().map(render_human).collect(),
+    }
+}
+
+fn render_human(explanation: &Explanation) -> String {
+    let mut out = format!(
+        "change {} (commit {})\n",
+        explanation.change_id, explanation.commit_id
+    );
+    match explanation.reason {
+        Some(reason) => out.push_str(&format!("  verdict: stale ({reason:?})\n")),
+        None => out.push_str("  verdict: not stale\n"),
+    }
+    out.push_str("  evolog, oldest first:\n");
+    for (index, entry) in explanation.evolog.iter().enumerate() {
+        let marker = if explanation.describe_index == Some(index) {
+            ">"
+        } else {
+            " "
+        };
+        out.push_str(&format!(
+            "  {marker} {index:>3} {} {}\n",
+            &entry.commit_id[..12.min(entry.commit_id.len())],
+            entry.subject
+        ));
+    }
+    match explanation.confidence {
+        Some(Confidence::High) => out.push_str("  > marks the last describe\n"),
+        Some(Confidence::Low) => {
+            out.push_str("  > marks the oldest entry (no describe found)\n");
+        }
+        None => {}
+    }
+    if !explanation.changed_files.is_empty() {
+        out.push_str("  changed since describe point: ");
+        out.push_str(&explanation.changed_files.join(", "));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use jj_lib::repo::Repo as _;
+    use jj_lib::repo_path::RepoPath;
+    use testutils::{TestRepo, create_tree};
+
+    use super::*;
+
+    #[test]
+    fn json_reports_describe_index() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let path = RepoPath::from_internal_string("file.txt").expect("valid path");
+
+        // Created undescribed (0), described (1), then edited (2).
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                create_tree(repo, &[(path, "v1")]),
+            )
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_description("feat: add file")
+            .write()
+            .expect("describe");
+        let repo = tx.commit("describe").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c3 = tx
+            .repo_mut()
+            .rewrite_commit(&c2)
+            .set_tree(create_tree(&repo, &[(path, "v2")]))
+            .write()
+            .expect("edit");
+        let repo = tx.commit("edit").expect("tx");
+
+        let explanation = explain(&repo, c3.id(), &CheckOptions::default()).expect("explain");
+        let json: serde_json::Value =
+            serde_json::from_str(&render(OutputFormat::Json, &[explanation])).expect("json");
+        let explained = &json["explanations"][0];
+
+        assert_eq!(explained["describeIndex"], 1);
+        assert_eq!(explained["confidence"], "high");
+        assert_eq!(explained["reason"], "contentChanged");
+        assert_eq!(explained["evolog"][1]["commitId"], c2.id().hex());
+        assert_eq!(explained["changedFiles"], serde_json::json!(["file.txt"]));
+        assert_ne!(
+            explained["evolog"][0]["descriptionHash"],
+            explained["evolog"][1]["descriptionHash"]
+        );
+    }
+
+    #[test]
+    fn fingerprint_changes_classify_each_path() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let kept = RepoPath::from_internal_string("kept.txt").expect("valid path");
+        let gone = RepoPath::from_internal_string("gone.txt").expect("valid path");
+        let new = RepoPath::from_internal_string("new.txt").expect("valid path");
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                create_tree(repo, &[(kept, "v1"), (gone, "g")]),
+            )
+            .set_description("feat: add files")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Described at creation, then edited: one file changed, one dropped,
+        // one new.
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(create_tree(&repo, &[(kept, "v2"), (new, "n")]))
+            .write()
+            .expect("edit");
+        let repo = tx.commit("edit").expect("tx");
+
+        let changes =
+            fingerprint_changes(&repo, c2.id(), &CheckOptions::default()).expect("changes");
+        assert_eq!(
+            changes,
+            [
+                ("gone.txt".to_owned(), FingerprintChange::Removed),
+                ("kept.txt".to_owned(), FingerprintChange::Modified),
+                ("new.txt".to_owned(), FingerprintChange::Added),
+            ]
+        );
+        assert_eq!(
+            render_fingerprint_changes("kxqpmnrlzwzv", &changes),
+            "change kxqpmnrlzwzv\n  removed  gone.txt\n  modified kept.txt\n  added    new.txt\n"
+        );
+    }
+}
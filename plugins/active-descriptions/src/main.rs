@@ -2,723 +2,7617 @@
 //!
 //! A description is "stale" when a change's content (diff from parent) has been
 //! modified since its description was last updated. Runs as a Claude Code
-//! PostToolUse (advisory) or Stop (blocking) hook.
+//! PostToolUse (advisory) or Stop (blocking) hook, or with `--check` for CI
+//! and pre-commit.
 //!
 //! Uses a single `jj log` subprocess for revset evaluation, then jj-lib for
 //! in-memory evolog walks and tree diffs — reducing overhead from O(N)
 //! subprocess calls to 1.
+//!
+//! Run with `--doctor` to check that the environment supports the hooks.
+//! Set `ACTIVE_DESCRIPTIONS_DEBUG` (or `ACTIVE_DESCRIPTIONS_LOG=debug`) to
+//! see why a run reported nothing, e.g. because `@` is already merged into
+//! `trunk()`. `ACTIVE_DESCRIPTIONS_LOG=trace` also logs each change's evolog
+//! walk and fingerprint comparison, for chasing false positives. Other
+//! values are `tracing` filter directives, e.g. `jj_lib=debug`.
+//! Set `ACTIVE_DESCRIPTIONS_DISABLE=1` to switch the hook off entirely, e.g.
+//! during a bisect.
+//! Set `ACTIVE_DESCRIPTIONS_REVSET` to check a different candidate revset
+//! without touching config, or pass `--revset`, which takes precedence.
+//! Set `ACTIVE_DESCRIPTIONS_JSON=1` to add the stale changes, structured as
+//! in `--format json`, to the advisory hook output.
+//! Set `ACTIVE_DESCRIPTIONS_STATE_DIR` to keep session state (retry counts,
+//! already-reported changes) somewhere other than the temp dir.
+
+mod config;
+mod explain;
+mod report;
 
-use std::collections::BTreeMap;
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
+use std::io::Write as _;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result, bail};
-use jj_lib::backend::CommitId;
+use anyhow::{Context as _, Result, bail};
+use jj_lib::backend::{CommitId, CopyId, FileId, SymlinkId, Timestamp, TreeId, TreeValue};
 use jj_lib::commit::Commit;
-use jj_lib::config::StackedConfig;
+use jj_lib::config::{ConfigSource, StackedConfig};
+use jj_lib::content_hash::blake2b_hash;
+use jj_lib::diff::{ContentDiff, DiffHunkKind};
 use jj_lib::evolution::walk_predecessors;
-use jj_lib::matchers::EverythingMatcher;
-use jj_lib::merge::Diff;
-use jj_lib::merge::MergedTreeValue;
-use jj_lib::repo::{ReadonlyRepo, Repo as _, RepoLoader, StoreFactories};
-use jj_lib::repo_path::RepoPathBuf;
+use jj_lib::gitignore::GitIgnoreFile;
+use jj_lib::hex_util::{decode_hex, encode_hex};
+use jj_lib::matchers::{Matcher, Visit, VisitDirs, VisitFiles};
+use jj_lib::merge::{Diff, Merge, MergedTreeValue};
+use jj_lib::merged_tree::MergedTree;
+use jj_lib::object_id::ObjectId as _;
+use jj_lib::repo::{ReadonlyRepo, Repo as _, RepoLoader, StoreFactories, StoreLoadError};
+use jj_lib::repo_path::{RepoPath, RepoPathBuf};
+use jj_lib::revset::ResolvedRevsetExpression;
 use jj_lib::settings::UserSettings;
 use pollster::FutureExt as _;
+use tokio::io::AsyncReadExt as _;
+use tracing::level_filters::LevelFilter;
+use tracing::{Level, debug, trace};
+use tracing_subscriber::EnvFilter;
 
-/// Maximum evolog entries to inspect per change (sanity bound).
+use crate::config::{Config, DescribePointStrategy, FileClass, Messages, Severity};
+use crate::report::OutputFormat;
+
+/// Maximum evolog entries to inspect per change (sanity bound), unless
+/// `max_evolog_entries` is configured.
 const MAX_EVOLOG_ENTRIES: usize = 200;
 
-/// Maximum retries before the stop hook gives up (prevents infinite loops).
+/// Maximum retries before the stop hook gives up (prevents infinite loops),
+/// unless `max_stop_retries` is configured.
 const MAX_STOP_RETRIES: u32 = 3;
 
+/// Exit code that blocks in stop mode: Claude Code's blocking code. See
+/// [`stop_exit_code`].
+const DEFAULT_STOP_EXIT_CODE: i32 = 2;
+
+/// Exit code of `--check` when anything is stale.
+const CHECK_EXIT_CODE: i32 = 1;
+
+/// `major.minor` of the linked jj-lib. Kept in sync with Cargo.toml by the
+/// jj-lib update workflow; `--doctor` warns when the `jj` CLI differs.
+const JJ_LIB_VERSION: &str = "0.38";
+
+/// Default candidate revset: in-flight, non-empty changes.
+const DEFAULT_REVSET: &str = "trunk()..@ ~ empty()";
+
+/// How often `--watch` polls for new operations.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Age past which `fingerprint_cache` entries are pruned. They never go out
+/// of date, but the commits they cover are long rewritten by then.
+const FINGERPRINT_CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long a `cache_workspace_root` entry is trusted.
+const WORKSPACE_ROOT_CACHE_TTL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct StalenessInfo {
     change_id_short: String,
+    /// Why the description was flagged.
+    reason: StaleReason,
     /// Files whose diff-from-parent changed since the last describe.
     changed_files: Vec<RepoPathBuf>,
+    /// How sure we are about the describe point the diff was compared to.
+    confidence: Confidence,
+    /// The evolog entry whose diff the description was written against, or
+    /// `None` for an empty description. With [`Confidence::Low`] this is the
+    /// oldest entry inspected, not a known describe.
+    described_commit_id: Option<CommitId>,
+    /// The current description, capped at `include_description_chars`, or
+    /// `None` unless that's set.
+    description: Option<String>,
+    /// Reason-specific detail, e.g. the banned phrase that matched.
+    detail: Option<String>,
+    /// Lines added and removed per changed file in the current diff, with
+    /// `line_counts`. Empty otherwise.
+    line_counts: BTreeMap<RepoPathBuf, LineCount>,
 }
 
-fn main() {
-    // Fail open: any error → exit 0 so we never block Claude.
-    if let Err(e) = run() {
-        // Only surface errors when debugging.
-        if env::var_os("ACTIVE_DESCRIPTIONS_DEBUG").is_some() {
-            #[allow(clippy::print_stderr)]
-            {
-                eprintln!("active-descriptions: {e:#}");
-            }
-        }
-    }
+/// Lines added and removed in one file, for `line_counts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineCount {
+    Text {
+        additions: usize,
+        deletions: usize,
+    },
+    /// Not UTF-8 on one side of the diff.
+    Binary,
 }
 
-fn run() -> Result<()> {
-    let stop_mode = env::args().nth(1).is_some_and(|a| a == "--stop");
-
-    // Gather candidate commit IDs via subprocess (evaluates revset with full
-    // CLI context, triggers working-copy snapshot).
-    let candidate_hex = gather_candidates();
-    if candidate_hex.is_empty() {
-        return Ok(());
-    }
-
-    // Load repo via jj-lib.
-    let repo = load_repo()?;
-
-    // Check each candidate for staleness.
-    let mut stale: Vec<StalenessInfo> = Vec::new();
-    for hex in &candidate_hex {
-        let commit_id = CommitId::try_from_hex(hex.as_bytes())
-            .with_context(|| format!("invalid commit id hex: {hex}"))?;
-        if let Some(info) = check_staleness(&repo, &commit_id)? {
-            stale.push(info);
+impl fmt::Display for LineCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text {
+                additions,
+                deletions,
+            } => write!(f, "+{additions}/-{deletions}"),
+            Self::Binary => f.write_str("binary"),
         }
     }
+}
 
-    stale.dedup_by(|a, b| a.change_id_short == b.change_id_short);
+/// The outcome of checking one change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Verdict {
+    Clean(CleanReason),
+    Stale(StalenessInfo),
+}
 
-    if stale.is_empty() {
-        // Descriptions are up to date — reset retry counter so the stop hook
-        // can re-arm if descriptions become stale later in the session.
-        if stop_mode {
-            reset_stop_retries();
+impl Verdict {
+    fn into_stale(self) -> Option<StalenessInfo> {
+        match self {
+            Self::Clean(_) => None,
+            Self::Stale(info) => Some(info),
         }
-        return Ok(());
     }
-
-    emit_output(&stale, stop_mode)
 }
 
-// ---------------------------------------------------------------------------
-// Subprocess: gather candidate commit IDs
-// ---------------------------------------------------------------------------
-
-/// Runs `jj log` to evaluate `trunk()..@ ~ empty()` and return full hex
-/// commit IDs. Returns an empty vec on any failure (not a jj repo, etc.).
-fn gather_candidates() -> Vec<String> {
-    let output = Command::new("jj")
-        .args([
-            "log",
-            "-r",
-            "trunk()..@ ~ empty()",
-            "--no-graph",
-            "-T",
-            r#"commit_id ++ "\n""#,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output();
-
-    let output = match output {
-        Ok(o) if o.status.success() => o,
-        _ => return Vec::new(),
-    };
+/// Why a description was considered current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+enum CleanReason {
+    /// The description matches `always_valid_descriptions`.
+    AlwaysValid,
+    /// The description is empty, but `skip_empty_check` is set.
+    EmptyCheckSkipped,
+    /// The description is empty, but the change is younger than
+    /// `empty_grace_period_secs`.
+    WithinGracePeriod,
+    /// The change has a single evolog entry: it was described when created
+    /// and hasn't been rewritten since.
+    DescribedAtCreation,
+    /// The diff is the same as when the description was last set.
+    UnchangedSinceDescribe,
+    /// The diff changed since the description was last set, but by fewer
+    /// than `min_changed_lines` lines.
+    BelowLineThreshold,
+    /// The change is a merge commit, and `skip_merges` is set.
+    MergeSkipped,
+    /// `only_mine` is set, and someone else authored the change.
+    NotMine,
+    /// The change was rewritten less than `grace_seconds` ago.
+    RecentlyEdited,
+    /// The root commit, which can't be redescribed.
+    Immutable,
+}
 
-    String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .map(str::trim)
-        .filter(|s| !s.is_empty())
-        .map(String::from)
-        .collect()
+impl fmt::Display for CleanReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AlwaysValid => "description is always valid",
+            Self::EmptyCheckSkipped => "empty description, but the empty check is off",
+            Self::WithinGracePeriod => "empty description, but within the grace period",
+            Self::DescribedAtCreation => "described at creation, never rewritten",
+            Self::UnchangedSinceDescribe => "diff unchanged since last described",
+            Self::BelowLineThreshold => "diff changed by fewer lines than min_changed_lines",
+            Self::MergeSkipped => "merge commit, and merges are skipped",
+            Self::NotMine => "authored by someone else",
+            Self::RecentlyEdited => "edited within the grace period",
+            Self::Immutable => "root commit, which is immutable",
+        })
+    }
 }
 
-// ---------------------------------------------------------------------------
-// jj-lib repo loading
-// ---------------------------------------------------------------------------
+/// Why a description was flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum StaleReason {
+    /// The change has content but no description.
+    EmptyDescription,
+    /// The change's diff changed since the description was last set.
+    ContentChanged,
+    /// The change touches more files than `require_body_over_files` allows
+    /// for a subject-only description.
+    MissingBody,
+    /// The description contains one of `banned_phrases`.
+    BannedPhrase,
+    /// The description only names the files it touches, e.g. "update
+    /// main.rs". Flagged with `flag_filename_restatements`.
+    RestatesFilename,
+    /// The subject has fewer than `min_subject_words` meaningful words
+    /// after its type prefix, e.g. `feat: wip`.
+    SubjectTooThin,
+    /// The description names only files a sibling change touches, none of
+    /// this change's, e.g. after a bad squash. Flagged with
+    /// `flag_mismatched_descriptions`.
+    DescriptionMismatchesDiff,
+    /// The subject doesn't match `require_pattern`, e.g. a missing issue
+    /// key.
+    PatternMismatch,
+    /// The description names a file the change touched when described but
+    /// has since dropped. Flagged with `flag_missing_references`.
+    ReferencesMissingFile,
+    /// The subject isn't a Conventional Commit. Flagged with
+    /// `require_conventional`.
+    NonConventionalFormat,
+    /// The trimmed description is shorter than `min_description_chars`.
+    DescriptionTooShort,
+    /// The subject is one of `placeholder_descriptions`, e.g. `wip`.
+    PlaceholderDescription,
+}
 
-/// Loads the repo at HEAD. Discovers the workspace root from `jj root`, then
-/// initializes a `RepoLoader` from the `.jj/repo` path.
-fn load_repo() -> Result<Arc<ReadonlyRepo>> {
-    let workspace_root = discover_workspace_root()?;
-    let repo_path = resolve_repo_path(&workspace_root.join(".jj").join("repo"))?;
+impl StaleReason {
+    /// Whether the change's content drifted since it was described, as
+    /// opposed to a description that fails a check on its own.
+    fn is_drift(self) -> bool {
+        matches!(self, Self::ContentChanged | Self::ReferencesMissingFile)
+    }
+}
 
-    let config = StackedConfig::with_defaults();
-    let settings =
-        UserSettings::from_config(config).context("failed to create UserSettings from defaults")?;
-    let store_factories = StoreFactories::default();
+/// Confidence in a staleness verdict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Confidence {
+    /// The description is empty, or the evolog shows where it was set.
+    High,
+    /// No describe transition was found in the evolog, so the diff was
+    /// compared against the oldest entry inspected. That's the creation
+    /// point unless the walk hit the `max_evolog_entries` cap.
+    Low,
+}
 
-    let loader = RepoLoader::init_from_file_system(&settings, &repo_path, &store_factories)
-        .context("failed to init repo loader")?;
-    let repo = loader
-        .load_at_head()
-        .context("failed to load repo at head")?;
+fn main() {
+    init_logging();
+    // Fail open: any error → exit 0 so we never block Claude.
+    if let Err(e) = run() {
+        // Only surface errors when debugging.
+        debug!("{e:#}");
+    }
+}
 
-    Ok(repo)
+/// Sends `tracing` events on stderr, filtered per [`log_directives`] from
+/// `ACTIVE_DESCRIPTIONS_LOG` and `ACTIVE_DESCRIPTIONS_DEBUG`.
+fn init_logging() {
+    let directives = log_directives(
+        env::var("ACTIVE_DESCRIPTIONS_LOG").ok().as_deref(),
+        env::var_os("ACTIVE_DESCRIPTIONS_DEBUG").is_some(),
+    );
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(directives))
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .without_time()
+        .init();
 }
 
-/// Resolves the repo path, following jj's workspace indirection.
-///
-/// In secondary workspaces, `.jj/repo` is a file containing the path to the
-/// primary workspace's repo directory rather than a directory itself.
-fn resolve_repo_path(path: &Path) -> Result<PathBuf> {
-    if path.is_file() {
-        let target = fs::read_to_string(path)
-            .with_context(|| format!("failed to read repo pointer at {}", path.display()))?;
-        let target = target.trim();
-        Ok(PathBuf::from(target))
+/// `EnvFilter` directives for `log`, the value of `ACTIVE_DESCRIPTIONS_LOG`,
+/// and `debug_set`, whether `ACTIVE_DESCRIPTIONS_DEBUG` is set. A bare level
+/// (`debug`, `trace`, any case) applies to this binary only, leaving jj-lib
+/// quiet; anything else is passed through as directives, e.g.
+/// `jj_lib=debug`. `debug_set` means at least `debug` for this binary.
+fn log_directives(log: Option<&str>, debug_set: bool) -> String {
+    const TARGET: &str = env!("CARGO_CRATE_NAME");
+    let scoped = |level: LevelFilter| format!("{TARGET}={}", level.to_string().to_lowercase());
+    let floor = if debug_set {
+        LevelFilter::DEBUG
     } else {
-        Ok(path.to_path_buf())
+        LevelFilter::OFF
+    };
+    match log.map(str::trim).filter(|log| !log.is_empty()) {
+        None => scoped(floor),
+        Some(log) => match log.parse::<LevelFilter>() {
+            Ok(level) => scoped(level.max(floor)),
+            Err(_) if debug_set => format!("{},{log}", scoped(floor)),
+            Err(_) => log.to_owned(),
+        },
     }
 }
 
-/// Gets the workspace root by running `jj root`.
-fn discover_workspace_root() -> Result<PathBuf> {
-    let output = Command::new("jj")
-        .args(["root"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()
-        .context("failed to run `jj root`")?;
+/// Parsed command-line arguments.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Args {
+    /// `--stop` runs as the blocking Stop hook; `--check` prints the report
+    /// (on stderr, or stdout for a report `--format`) and exits 1 if
+    /// anything is stale, for CI and pre-commit, with no retry budget.
+    /// `--check` overrides `--stop`.
+    mode: Mode,
+    /// `--doctor`: validate the environment and print a report.
+    doctor: bool,
+    /// `--format <FORMAT>`: print a report on stdout instead of speaking the
+    /// hook protocol. Overrides `--stop`.
+    format: OutputFormat,
+    /// `--all-stale`: in advisory mode, report every stale change rather than
+    /// only those that became stale since the previous run.
+    all_stale: bool,
+    /// `--no-empty-check`: don't flag changes with empty descriptions. Same
+    /// as `skip_empty_check = true` in config.
+    no_empty_check: bool,
+    /// `--changed-only`: report only changes whose diff drifted since they
+    /// were described, leaving out empty descriptions and the format checks.
+    changed_only: bool,
+    /// `--threads <N>`: worker threads for candidate checking. `0` (the
+    /// default) auto-detects; `1` checks candidates sequentially on the main
+    /// thread, which helps rule concurrency in or out when debugging.
+    threads: usize,
+    /// `--print-candidates`: print the candidate revset and the commit ids it
+    /// evaluates to, without checking staleness.
+    print_candidates: bool,
+    /// `--rev <REV>`: check only what `REV` resolves to (a revset, commit id
+    /// or change id) instead of the candidate revset.
+    rev: Option<String>,
+    /// `--revset <REVSET>`: check this candidate revset instead of the
+    /// configured one. Overrides `ACTIVE_DESCRIPTIONS_REVSET`.
+    revset: Option<String>,
+    /// `--profile`: print wall-clock time per phase on stderr.
+    profile: bool,
+    /// `--explain`: show how each verdict was reached (the evolog walk, the
+    /// chosen describe point and the fingerprint diff) instead of reporting.
+    /// Honors `--format json`.
+    explain: bool,
+    /// `--print-diff <REV>`: like `--rev REV`, but print how each file
+    /// differs between the describe-point and current fingerprints (added,
+    /// removed or modified) instead of reporting.
+    print_diff: bool,
+    /// `--watch`: re-check after every repo operation until interrupted,
+    /// printing a report each time (`--format text` unless given).
+    watch: bool,
+    /// `--verbose` (or `ACTIVE_DESCRIPTIONS_VERBOSE`): also explain, on
+    /// stderr, why each clean candidate wasn't flagged.
+    verbose: bool,
+    /// `--no-reset`: leave the stop hook's retry count alone on a clean
+    /// result, for debugging stop mode across runs.
+    no_reset: bool,
+    /// `--config-set <KEY=VALUE>` (repeatable): override a config setting
+    /// for this run, over every config source.
+    config_set: Vec<String>,
+    /// `--output-fd <N>`: write what would go to stdout (the chosen format,
+    /// or the hook JSON) to file descriptor `N` instead.
+    output_fd: Option<u32>,
+}
 
-    if !output.status.success() {
-        bail!("not a jj repo (jj root failed)");
+impl Args {
+    /// Parses arguments (without the program name). Flags taking a value
+    /// accept both `--flag VALUE` and `--flag=VALUE`.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .with_context(|| format!("{flag} requires a value"))
+            };
+            match flag.as_str() {
+                "--stop" => parsed.mode = parsed.mode.max(Mode::Stop),
+                "--check" => parsed.mode = Mode::Check,
+                "--doctor" => parsed.doctor = true,
+                "--all-stale" => parsed.all_stale = true,
+                "--no-empty-check" => parsed.no_empty_check = true,
+                "--changed-only" => parsed.changed_only = true,
+                "--print-candidates" => parsed.print_candidates = true,
+                "--profile" => parsed.profile = true,
+                "--explain" => parsed.explain = true,
+                "--watch" => parsed.watch = true,
+                "--verbose" => parsed.verbose = true,
+                "--no-reset" => parsed.no_reset = true,
+                "--format" => parsed.format = value()?.parse()?,
+                "--rev" => parsed.rev = Some(value()?),
+                "--revset" => parsed.revset = Some(value()?),
+                "--config-set" => parsed.config_set.push(value()?),
+                "--print-diff" => {
+                    parsed.rev = Some(value()?);
+                    parsed.print_diff = true;
+                }
+                "--output-fd" => {
+                    let value = value()?;
+                    let fd = value
+                        .parse()
+                        .with_context(|| format!("invalid --output-fd value: {value}"))?;
+                    parsed.output_fd = Some(fd);
+                }
+                "--threads" => {
+                    let value = value()?;
+                    parsed.threads = value
+                        .parse()
+                        .with_context(|| format!("invalid --threads value: {value}"))?;
+                }
+                other => bail!("unknown argument: {other}"),
+            }
+        }
+        Ok(parsed)
     }
-
-    let root = String::from_utf8(output.stdout)
-        .context("jj root output is not utf-8")?
-        .trim()
-        .to_owned();
-
-    Ok(PathBuf::from(root))
 }
 
-// ---------------------------------------------------------------------------
-// Staleness detection
-// ---------------------------------------------------------------------------
-
-/// Checks whether a commit's description is stale relative to its content.
-///
-/// Returns `None` when the description is current, or `Some(StalenessInfo)`
-/// with the change ID and list of files whose diff-from-parent changed since
-/// the description was last set.
-///
-/// A description is stale if:
-/// - The commit has a non-empty diff but an empty description, OR
-/// - The commit's diff-from-parent has changed since the description was last
-///   set (determined by walking the evolution log and comparing tree diffs).
-///
-/// This compares actual diffs rather than using heuristics about tree/parent
-/// change ordering, which avoids false positives from splits, squashes, and
-/// rebases that alter the tree without changing the logical content.
-fn check_staleness(repo: &ReadonlyRepo, commit_id: &CommitId) -> Result<Option<StalenessInfo>> {
-    let commit = repo.store().get_commit(commit_id)?;
-
-    // ChangeId::Display uses reverse_hex (the user-facing jj format).
-    let full_change_id = commit.change_id().to_string();
-    let change_id_short = full_change_id[..full_change_id.len().min(12)].to_owned();
+fn run() -> Result<()> {
+    run_unless_disabled(
+        env::var_os("ACTIVE_DESCRIPTIONS_DISABLE").as_deref(),
+        env::args().skip(1),
+        &JjCli,
+    )
+}
 
-    // Empty description on a non-empty change is always stale.
-    // Report every file in the current diff as changed.
-    if commit.description().is_empty() {
-        let current_diff = commit_diff_fingerprint(repo, &commit)?;
-        let changed_files: Vec<RepoPathBuf> = current_diff.into_keys().collect();
-        return Ok(Some(StalenessInfo {
-            change_id_short,
-            changed_files,
-        }));
+/// Parses `argv` and runs, unless `disable` (the value of
+/// `ACTIVE_DESCRIPTIONS_DISABLE`) switches the hook off. Disabling skips
+/// everything, argument parsing included, so a bisect or scripted rebase
+/// can silence every mode without touching config.
+fn run_unless_disabled(
+    disable: Option<&OsStr>,
+    argv: impl IntoIterator<Item = String>,
+    runner: &dyn CommandRunner,
+) -> Result<()> {
+    if disable.is_some_and(|value| !value.is_empty() && value != "0") {
+        debug!("disabled by ACTIVE_DESCRIPTIONS_DISABLE");
+        return Ok(());
     }
+    let args = Args::parse(argv)?;
+    let ctx = Context::live(runner);
+    let output = run_with(&args, &ctx);
+    ctx.flush_notes();
+    deliver(&args, output?);
+    Ok(())
+}
 
-    // Collect evolution entries (newest first from walk_predecessors, so we
-    // reverse to get chronological order).
-    let mut entries = Vec::new();
-    for result in walk_predecessors(repo, std::slice::from_ref(commit_id)) {
-        let entry = result.context("evolog walk failed")?;
-        entries.push(entry);
-        if entries.len() >= MAX_EVOLOG_ENTRIES {
-            break;
+/// What a run talks to. [`Context::live`] is the jj CLI, the on-disk repo
+/// and the temp dir; tests substitute a fake runner, a `TestRepo` and a
+/// scratch directory, then assert on the [`Output`] and notes of
+/// [`run_with`].
+struct Context<'a> {
+    /// Runs `jj`, which also supplies the candidates.
+    runner: &'a dyn CommandRunner,
+    /// Loads the repo at [`RunContext::repo_path`].
+    repo_loader: &'a dyn Fn(&Path, &UserSettings) -> Result<Arc<ReadonlyRepo>>,
+    /// Directory holding session-scoped state files.
+    state_dir: PathBuf,
+    /// The Claude session id, scoping those files.
+    session_id: String,
+    /// Lines for stderr, e.g. `--verbose` or `--profile` output, collected
+    /// during a run and printed by [`flush_notes`](Self::flush_notes).
+    notes: RefCell<Vec<String>>,
+}
+
+impl<'a> Context<'a> {
+    fn live(runner: &'a dyn CommandRunner) -> Self {
+        Self {
+            runner,
+            repo_loader: &load_repo,
+            state_dir: state_dir_from(env::var_os("ACTIVE_DESCRIPTIONS_STATE_DIR")),
+            session_id: session_id(),
+            notes: RefCell::default(),
         }
     }
 
-    // walk_predecessors yields newest-first; reverse for chronological.
-    entries.reverse();
-
-    if entries.len() < 2 {
-        // Single entry (freshly created) — if it has a description, it's fine.
-        return Ok(None);
+    /// Queues each line of `text` for stderr.
+    fn note(&self, text: &str) {
+        self.notes
+            .borrow_mut()
+            .extend(text.lines().map(str::to_owned));
     }
 
-    // Find the evolog entry where the description was last changed.
-    let mut last_described_commit: Option<&Commit> = None;
-    for i in (1..entries.len()).rev() {
-        if entries[i].commit.description() != entries[i - 1].commit.description() {
-            last_described_commit = Some(&entries[i].commit);
-            break;
+    /// Prints the queued notes on stderr, emptying the queue.
+    fn flush_notes(&self) {
+        for note in self.notes.take() {
+            #[allow(clippy::print_stderr)]
+            {
+                eprintln!("{note}");
+            }
         }
     }
 
-    // If the description was never changed, it was established at the first
-    // evolog entry. We still need to compare its diff to the current diff to
-    // catch content edits that happened after the initial describe.
-    let described_commit = last_described_commit.unwrap_or(&entries[0].commit);
-
-    // Compare the diff-from-parent at describe-time vs now. If identical,
-    // the logical content hasn't changed and the description is still valid.
-    let described_diff = commit_diff_fingerprint(repo, described_commit)?;
-    let current_diff = commit_diff_fingerprint(repo, &commit)?;
-
-    if described_diff == current_diff {
-        return Ok(None);
+    /// Path of a session-scoped state file, e.g. the workspace root cache.
+    fn state_file(&self, kind: &str) -> PathBuf {
+        self.state_dir
+            .join(format!("claude-stale-desc-{kind}-{}", self.session_id))
     }
 
-    let changed_files = diff_fingerprint_changes(&described_diff, &current_diff);
-
-    Ok(Some(StalenessInfo {
-        change_id_short,
-        changed_files,
-    }))
+    /// Like [`state_file`](Self::state_file), but also scoped to one
+    /// workspace, e.g. the stop hook's retry counter, so two repos in the
+    /// same session (or without one) don't share it.
+    fn workspace_state_file(&self, kind: &str, workspace_root: &Path) -> PathBuf {
+        let root = workspace_root.to_string_lossy().into_owned();
+        let hash = encode_hex(&blake2b_hash(&root));
+        self.state_dir.join(format!(
+            "claude-stale-desc-{kind}-{}-{}",
+            self.session_id,
+            &hash[..16]
+        ))
+    }
 }
 
-/// Computes a fingerprint of a commit's diff from its parent(s).
-///
-/// Returns a sorted map of `(path → (before, after))` tree value pairs. Two
-/// commits have the same logical content iff their fingerprints are equal,
-/// regardless of what parents they sit on.
-fn commit_diff_fingerprint(
-    repo: &ReadonlyRepo,
-    commit: &Commit,
-) -> Result<BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>> {
-    let tree = commit.tree();
-    let parent_tree = commit.parent_tree(repo)?;
-
-    let mut fingerprint = BTreeMap::new();
-    let mut stream = parent_tree.diff_stream(&tree, &EverythingMatcher);
-
-    async {
-        use futures::StreamExt as _;
-        while let Some(entry) = stream.next().await {
-            let diff = entry.values?;
-            fingerprint.insert(entry.path, diff);
-        }
-        anyhow::Ok(())
+/// The state directory: `ACTIVE_DESCRIPTIONS_STATE_DIR` (`from_env`) if set
+/// and non-empty, else the temp dir.
+fn state_dir_from(from_env: Option<OsString>) -> PathBuf {
+    match from_env {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::temp_dir(),
     }
-    .block_on()?;
+}
 
-    Ok(fingerprint)
+/// What a run prints, returned rather than printed so tests can assert on
+/// it. [`emit`] acts on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Output {
+    Nothing,
+    /// Printed on stdout as is.
+    Stdout(String),
+    /// Stop mode blocking, or `--check` failing: `message` on stderr, then
+    /// exit with `exit_code`.
+    Block {
+        message: String,
+        exit_code: i32,
+    },
+    /// `--check` failing with a report `--format`: `text` on stdout, then
+    /// exit with `exit_code`.
+    Report {
+        text: String,
+        exit_code: i32,
+    },
 }
 
-/// Returns the set of paths whose diff-from-parent entry differs between two
-/// fingerprints. This is the set of files that "changed" between two points
-/// in a commit's evolution.
-fn diff_fingerprint_changes(
-    described: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
-    current: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
-) -> Vec<RepoPathBuf> {
-    let mut changed = Vec::new();
+/// [`emit`]s `output`, or with `--output-fd` sends its stdout there.
+fn deliver(args: &Args, output: Output) {
+    match args.output_fd {
+        Some(fd) => emit(redirect_output(output, fd)),
+        None => emit(output),
+    }
+}
 
-    // Paths present in current but absent or different in described.
-    for (path, cur_diff) in current {
-        match described.get(path) {
-            Some(desc_diff) if desc_diff == cur_diff => {}
-            _ => changed.push(path.clone()),
-        }
+/// Writes `output`'s stdout text to file descriptor `fd` (via `/dev/fd`),
+/// leaving the rest to [`emit`]. Fails open: a descriptor that can't be
+/// written is logged under debug and the text dropped.
+fn redirect_output(output: Output, fd: u32) -> Output {
+    let (text, exit_code) = match output {
+        Output::Stdout(text) => (text, None),
+        Output::Report { text, exit_code } => (text, Some(exit_code)),
+        other => return other,
+    };
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/dev/fd/{fd}"))
+        .and_then(|mut file| file.write_all(text.as_bytes()));
+    if let Err(e) = written {
+        debug!("failed to write output to fd {fd}: {e}");
     }
+    match exit_code {
+        Some(exit_code) => Output::Report {
+            text: String::new(),
+            exit_code,
+        },
+        None => Output::Nothing,
+    }
+}
 
-    // Paths removed from the diff (present in described, absent in current).
-    for path in described.keys() {
-        if !current.contains_key(path) {
-            changed.push(path.clone());
+/// Prints `output`, exiting the process if it blocks.
+fn emit(output: Output) {
+    match output {
+        Output::Nothing => {}
+        Output::Stdout(text) => {
+            #[allow(clippy::print_stdout)]
+            {
+                print!("{text}");
+            }
+        }
+        Output::Block { message, exit_code } => {
+            #[allow(clippy::print_stderr)]
+            {
+                eprintln!("{message}");
+            }
+            std::process::exit(exit_code);
+        }
+        Output::Report { text, exit_code } => {
+            #[allow(clippy::print_stdout)]
+            {
+                print!("{text}");
+            }
+            let _ = std::io::stdout().flush();
+            std::process::exit(exit_code);
         }
     }
+}
 
-    changed.sort();
-    changed
+/// Per-run facts about the workspace, resolved once and threaded through so
+/// each step doesn't re-spawn `jj root` or re-read the repo pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RunContext {
+    /// Workspace root, as reported by `jj root`.
+    workspace_root: PathBuf,
+    /// The repo directory, after following secondary-workspace indirection.
+    repo_path: PathBuf,
 }
 
-// ---------------------------------------------------------------------------
-// Output
-// ---------------------------------------------------------------------------
+impl RunContext {
+    fn discover(runner: &dyn CommandRunner) -> Result<Self> {
+        Self::at(discover_workspace_root(runner)?)
+    }
 
-/// Emits output appropriate for the hook mode.
-///
-/// - **Stop mode**: stderr + exit 2 to block session exit.
-/// - **Advisory**: JSON on stdout for Claude Code hook protocol.
-fn emit_output(stale: &[StalenessInfo], stop_mode: bool) -> Result<()> {
-    let msg = format_staleness_message(stale);
-
-    if stop_mode {
-        emit_stop(&format!(
-            "{msg}\n\n\
-             You MUST update all stale descriptions before stopping. \
-             Ensure the active-descriptions:describe skill is loaded, \
-             then follow it for each stale change."
-        ))
-    } else {
-        emit_advisory(&msg)
+    fn at(workspace_root: PathBuf) -> Result<Self> {
+        let repo_path = resolve_repo_path(&workspace_root.join(".jj").join("repo"))?;
+        Ok(Self {
+            workspace_root,
+            repo_path,
+        })
     }
 }
 
-/// Builds a human-readable staleness summary including changed file paths.
-fn format_staleness_message(stale: &[StalenessInfo]) -> String {
-    use std::fmt::Write as _;
+/// Wall-clock timings for `--profile`. Noted for stderr on drop, so early
+/// returns report the phases that ran.
+#[derive(Debug)]
+struct Profile<'a> {
+    enabled: bool,
+    phases: Vec<(&'static str, Duration)>,
+    ctx_notes: &'a RefCell<Vec<String>>,
+}
 
-    let mut msg = String::new();
-    for (i, info) in stale.iter().enumerate() {
-        if i > 0 {
-            msg.push('\n');
+impl<'a> Profile<'a> {
+    fn new(enabled: bool, ctx_notes: &'a RefCell<Vec<String>>) -> Self {
+        Self {
+            enabled,
+            phases: Vec::new(),
+            ctx_notes,
         }
-        let _ = write!(
-            msg,
-            "Stale description: change {} modified since last described.",
-            info.change_id_short
-        );
-        if !info.changed_files.is_empty() {
-            let files: Vec<_> = info
-                .changed_files
-                .iter()
-                .map(|f| f.as_internal_file_string().to_owned())
-                .collect();
-            let _ = write!(msg, "\n  Changed: {}", files.join(", "));
+    }
+
+    /// Runs `f`, recording its duration as `phase` when enabled.
+    fn time<R>(&mut self, phase: &'static str, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        if self.enabled {
+            self.phases.push((phase, start.elapsed()));
         }
+        result
+    }
+
+    fn render(&self) -> String {
+        self.phases
+            .iter()
+            .map(|(phase, elapsed)| {
+                format!(
+                    "profile: {phase}: {:.1}ms\n",
+                    elapsed.as_secs_f64() * 1000.0
+                )
+            })
+            .collect()
     }
-    msg
 }
 
-/// Advisory mode: JSON on stdout for Claude Code PostToolUse hook.
-fn emit_advisory(msg: &str) -> Result<()> {
-    let output = serde_json::json!({
-        "hookSpecificOutput": {
-            "additionalContext": msg
+impl Drop for Profile<'_> {
+    fn drop(&mut self) {
+        if self.enabled {
+            self.ctx_notes
+                .borrow_mut()
+                .extend(self.render().lines().map(str::to_owned));
         }
-    });
-    #[allow(clippy::print_stdout)]
-    {
-        println!("{output}");
     }
-    Ok(())
 }
 
-/// Removes the session-scoped retry file so the stop hook can re-arm.
-/// Called when descriptions are found to be up-to-date.
-fn reset_stop_retries() {
-    let session_id = env::var("CLAUDE_SESSION_ID").unwrap_or_else(|_| "unknown".into());
-    let retry_file = env::temp_dir().join(format!("claude-stale-desc-retries-{session_id}"));
-    let _ = fs::remove_file(&retry_file);
-}
+fn run_with(args: &Args, ctx: &Context<'_>) -> Result<Output> {
+    let runner = ctx.runner;
+    let mode = args.mode;
+    let mut profile = Profile::new(args.profile, &ctx.notes);
 
-/// Stop mode: message on stderr, exit 2. Includes retry cap to prevent
-/// infinite loops when Claude can't/won't fix the descriptions.
-///
-/// The retry counter resets per prompt via a `UserPromptSubmit` hook, so each
-/// user prompt gets a fresh budget of [`MAX_STOP_RETRIES`] attempts.
-fn emit_stop(msg: &str) -> Result<()> {
-    let session_id = env::var("CLAUDE_SESSION_ID").unwrap_or_else(|_| "unknown".into());
-    let retry_file = env::temp_dir().join(format!("claude-stale-desc-retries-{session_id}"));
+    if args.doctor {
+        return Ok(run_doctor(ctx));
+    }
+    if args.watch {
+        run_watch(args, ctx)?;
+        return Ok(Output::Nothing);
+    }
 
-    let retries: u32 = fs::read_to_string(&retry_file)
-        .ok()
-        .and_then(|s| s.trim().parse().ok())
-        .unwrap_or(0);
+    if let Err(e) = fs::create_dir_all(&ctx.state_dir) {
+        debug!(
+            "failed to create state dir {}: {e}",
+            ctx.state_dir.display()
+        );
+    }
 
-    if retries >= MAX_STOP_RETRIES {
-        return Ok(());
+    // Load jj's config and ours; the latter may override the revset.
+    let cwd = env::current_dir().unwrap_or_default();
+    let root_cache = ctx.state_file("root");
+    let cached_root = read_cached_root(&root_cache, &cwd, SystemTime::now());
+    let cache_hit = cached_root.is_some();
+    let workspace = match cached_root {
+        Some(root) => RunContext::at(root)?,
+        None => RunContext::discover(runner)?,
+    };
+    let settings = load_user_settings(&workspace.repo_path)?;
+    let overrides = config::parse_overrides(&args.config_set)?;
+    let mut config = load_config(&workspace.workspace_root, settings.config(), &overrides);
+    match (config.cache_workspace_root, cache_hit) {
+        (true, false) => {
+            write_cached_root(
+                &root_cache,
+                &cwd,
+                &workspace.workspace_root,
+                SystemTime::now(),
+            );
+        }
+        (false, true) => {
+            let _ = fs::remove_file(&root_cache);
+        }
+        _ => {}
+    }
+    config.skip_empty_check |= args.no_empty_check;
+    apply_revset_override(
+        args.revset.as_deref(),
+        env::var("ACTIVE_DESCRIPTIONS_REVSET").ok().as_deref(),
+        &overrides,
+        &mut config,
+    );
+    apply_since_remote(runner, &mut config);
+    if defer_for_pending_edits(runner, &config) {
+        debug!("working copy has pending edits; deferring the check");
+        return Ok(Output::Nothing);
     }
 
-    fs::write(&retry_file, (retries + 1).to_string())
-        .with_context(|| format!("failed to write retry file: {}", retry_file.display()))?;
+    if args.print_candidates {
+        return Ok(Output::Stdout(format_candidates(runner, config.revset())));
+    }
 
-    #[allow(clippy::print_stderr)]
-    {
-        eprintln!("{msg}");
+    // Gather candidate commit IDs via subprocess (evaluates revset with full
+    // CLI context, triggers working-copy snapshot).
+    let mut candidate_hex = profile.time("gather_candidates", || match &args.rev {
+        Some(rev) => resolve_rev(runner, rev, &mut ctx.notes.borrow_mut()),
+        None => Ok(gather_candidates(runner, config.revset())),
+    })?;
+    let truncated = cap_candidates(&mut candidate_hex, config.max_candidates());
+    if let Some(warning) = &truncated {
+        debug!("{warning}");
+    }
+    debug!(
+        "{} candidates from {}: {}",
+        candidate_hex.len(),
+        config.revset(),
+        candidate_hex.join(" ")
+    );
+    if candidate_hex.is_empty() {
+        if tracing::enabled!(Level::DEBUG) {
+            debug!("{}", explain_no_candidates(runner, config.revset()));
+        }
+        // Shell conditionals on `--format count` need a number either way.
+        if args.format == OutputFormat::Count {
+            return Ok(Output::Stdout(report::render(
+                args.format,
+                &report::Outcome::default(),
+            )));
+        }
+        return Ok(Output::Nothing);
     }
 
-    std::process::exit(2);
+    // Load repo via jj-lib.
+    let repo = profile.time("load_repo", || {
+        (ctx.repo_loader)(&workspace.repo_path, &settings)
+    })?;
+
+    let commit_ids = retain_existing_commits(&repo, parse_commit_ids(&candidate_hex)?);
+    let change_ids = commit_ids
+        .iter()
+        .map(|id| Ok(repo.store().get_commit(id)?.change_id().to_string()))
+        .collect::<Result<Vec<_>>>()?;
+    let change_id_len = unambiguous_change_id_len(&change_ids, config.change_id_length());
+    let fingerprint_cache = config
+        .fingerprint_cache
+        .then(|| ctx.state_dir.join("active-descriptions-fingerprints"));
+    if let Some(cache_dir) = &fingerprint_cache {
+        prune_fingerprint_cache(cache_dir, SystemTime::now());
+    }
+    let options = CheckOptions {
+        base: resolve_fingerprint_base(runner, &config),
+        config,
+        now_millis: now_millis(),
+        fingerprint_cache,
+        user_email: Some(settings.user_email().to_owned()).filter(|email| !email.is_empty()),
+        change_id_len: Some(change_id_len),
+        changed_only: args.changed_only,
+    };
+
+    if args.explain {
+        let explanations = commit_ids
+            .iter()
+            .map(|commit_id| explain::explain(&repo, commit_id, &options))
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Output::Stdout(explain::render(args.format, &explanations)));
+    }
+    if args.print_diff {
+        let mut out = String::new();
+        for commit_id in &commit_ids {
+            let changes = explain::fingerprint_changes(&repo, commit_id, &options)?;
+            let commit = repo.store().get_commit(commit_id)?;
+            let change_id = change_id_prefix(&commit, options.change_id_len());
+            out.push_str(&explain::render_fingerprint_changes(&change_id, &changes));
+        }
+        return Ok(Output::Stdout(out));
+    }
+
+    // Check each candidate for staleness.
+    let threads = match args.threads {
+        0 => default_thread_count(),
+        n => n,
+    };
+    let verdicts = profile.time("check_candidates", || {
+        check_verdicts(&repo, &commit_ids, &options, threads)
+    })?;
+    if args.verbose || env::var_os("ACTIVE_DESCRIPTIONS_VERBOSE").is_some() {
+        ctx.note(&format_clean_verdicts(&commit_ids, &verdicts));
+    }
+    let records = if args.format == OutputFormat::Ndjson {
+        change_records(&repo, &commit_ids, &verdicts, options.change_id_len())?
+    } else {
+        Vec::new()
+    };
+    let stack = if options.config.stack_positions && args.format != OutputFormat::Hook {
+        stale_stack_positions(runner, &repo, &options, &commit_ids, &verdicts)
+    } else {
+        Vec::new()
+    };
+    let mut stale = merge_duplicate_changes(
+        verdicts
+            .into_iter()
+            .filter_map(Verdict::into_stale)
+            .collect(),
+    );
+    if args.changed_only {
+        stale.retain(|info| info.reason.is_drift());
+    }
+
+    let checked = checked_change_ids(&repo, &commit_ids, options.change_id_len())?;
+    if args.format != OutputFormat::Hook {
+        let outcome = report::Outcome {
+            stale: &stale,
+            checked: &checked,
+            records: &records,
+            stack: &stack,
+            truncated: truncated.is_some(),
+            messages: &options.config.messages,
+            remediation_command: options.config.remediation_command(),
+        };
+        let text = report::render(args.format, &outcome);
+        // `--check` fails on stale changes whatever the format.
+        if mode == Mode::Check && !stale.is_empty() {
+            return Ok(Output::Report {
+                text,
+                exit_code: CHECK_EXIT_CODE,
+            });
+        }
+        return Ok(Output::Stdout(text));
+    }
+
+    // Counted before the advisory filter, so the summary gives the scope.
+    let counts = StaleCounts {
+        stale: stale.len(),
+        checked: checked.len(),
+    };
+    // Advisory runs fire after every tool call; repeating the same stale
+    // changes each time is noise, so only surface new ones.
+    if mode == Mode::Advisory && !args.all_stale {
+        let seen = ctx.workspace_state_file("seen", &workspace.workspace_root);
+        let full_ids = full_change_ids(&repo, &commit_ids, options.change_id_len())?;
+        stale = retain_newly_stale(&seen, stale, &full_ids);
+    }
+    let retry_file = ctx.workspace_state_file("retries", &workspace.workspace_root);
+
+    if stale.is_empty() {
+        // Descriptions are up to date — reset retry counter so the stop hook
+        // can re-arm if descriptions become stale later in the session.
+        if mode == Mode::Stop && !args.no_reset {
+            reset_stop_retries(&retry_file);
+        }
+        return Ok(Output::Nothing);
+    }
+
+    hook_output(
+        &stale,
+        counts,
+        mode,
+        &options.config,
+        &retry_file,
+        &ctx.session_id,
+    )
 }
 
-#[cfg(test)]
-#[allow(clippy::expect_used)]
-mod tests {
-    use super::*;
-    use testutils::{TestRepo, create_tree};
+// ---------------------------------------------------------------------------
+// Watch mode
+// ---------------------------------------------------------------------------
 
-    /// Helper: create a tree with the given file contents.
-    fn tree(
-        repo: &Arc<ReadonlyRepo>,
-        files: &[(&str, &str)],
-    ) -> jj_lib::merged_tree::MergedTree {
-        let path_contents: Vec<_> = files
+/// Reports repo operation changes to `--watch`. Abstracted so tests can
+/// script the changes.
+trait OpWatcher {
+    /// Blocks until the repo's operation changes and returns the new op
+    /// heads, or `None` to stop watching.
+    fn wait_for_change(&mut self) -> Result<Option<String>>;
+}
+
+/// Polls jj's op heads directory every [`WATCH_POLL_INTERVAL`]. Listing one
+/// small directory is cheap enough to leave running while you work.
+struct OpHeadsPoller {
+    heads_dir: PathBuf,
+    last: String,
+}
+
+impl OpHeadsPoller {
+    fn new(repo_path: &Path) -> Self {
+        let heads_dir = repo_path.join("op_heads").join("heads");
+        let last = read_op_heads(&heads_dir);
+        Self { heads_dir, last }
+    }
+}
+
+impl OpWatcher for OpHeadsPoller {
+    fn wait_for_change(&mut self) -> Result<Option<String>> {
+        loop {
+            thread::sleep(WATCH_POLL_INTERVAL);
+            let heads = read_op_heads(&self.heads_dir);
+            if heads != self.last {
+                self.last.clone_from(&heads);
+                return Ok(Some(heads));
+            }
+        }
+    }
+}
+
+/// The current op head ids (file names under `op_heads/heads`), sorted and
+/// comma-joined. Empty if the directory can't be read.
+fn read_op_heads(heads_dir: &Path) -> String {
+    let mut heads: Vec<String> = fs::read_dir(heads_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    heads.sort();
+    heads.join(",")
+}
+
+/// `--watch`: checks once, then again after every operation, until
+/// interrupted. Each check is a regular advisory run reporting every stale
+/// change.
+fn run_watch(args: &Args, ctx: &Context<'_>) -> Result<()> {
+    let workspace = RunContext::discover(ctx.runner)?;
+    let once = Args {
+        mode: Mode::Advisory,
+        watch: false,
+        all_stale: true,
+        format: match args.format {
+            OutputFormat::Hook => OutputFormat::Text,
+            format => format,
+        },
+        ..args.clone()
+    };
+    let check = || {
+        let output = run_with(&once, ctx);
+        ctx.flush_notes();
+        output.map(|output| deliver(&once, output))
+    };
+    if let Err(e) = check() {
+        debug!("{e:#}");
+    }
+    watch_loop(&mut OpHeadsPoller::new(&workspace.repo_path), check)?;
+    Ok(())
+}
+
+/// Runs `check` after each change `watcher` reports, until it stops, and
+/// returns the number of re-checks. A failing check is logged under debug
+/// and watching continues.
+fn watch_loop(watcher: &mut dyn OpWatcher, mut check: impl FnMut() -> Result<()>) -> Result<usize> {
+    let mut checks = 0;
+    while let Some(op_heads) = watcher.wait_for_change()? {
+        debug!("operation changed ({op_heads}); re-checking");
+        if let Err(e) = check() {
+            debug!("{e:#}");
+        }
+        checks += 1;
+    }
+    Ok(checks)
+}
+
+// ---------------------------------------------------------------------------
+// Subprocess: gather candidate commit IDs
+// ---------------------------------------------------------------------------
+
+/// Runs `jj` subcommands. Abstracted so tests can substitute canned output.
+trait CommandRunner {
+    /// Runs `jj` with `args` and returns its stdout. Fails if the process
+    /// can't be spawned or exits unsuccessfully.
+    fn run_jj(&self, args: &[&str]) -> Result<String>;
+}
+
+/// Runs the real `jj` binary from `PATH`.
+struct JjCli;
+
+impl CommandRunner for JjCli {
+    fn run_jj(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("jj")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .with_context(|| format!("failed to run `jj {}`", args.join(" ")))?;
+
+        if !output.status.success() {
+            bail!(
+                "`jj {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        String::from_utf8(output.stdout)
+            .with_context(|| format!("`jj {}` output is not utf-8", args.join(" ")))
+    }
+}
+
+/// `ACTIVE_DESCRIPTIONS_REVSET`'s value (`from_env`), trimmed, unless it's
+/// unset or blank.
+fn revset_from_env(from_env: Option<&str>) -> Option<String> {
+    from_env
+        .map(str::trim)
+        .filter(|revset| !revset.is_empty())
+        .map(String::from)
+}
+
+/// Replaces the configured revset (and `since_remote`) with the one from
+/// `--revset`, or else from `ACTIVE_DESCRIPTIONS_REVSET`. A
+/// `--config-set revset=...` beats the environment but not the flag.
+fn apply_revset_override(
+    flag: Option<&str>,
+    from_env: Option<&str>,
+    overrides: &toml::Table,
+    config: &mut Config,
+) {
+    let revset = match flag {
+        Some(flag) => Some(flag.to_owned()),
+        None if overrides.contains_key("revset") => None,
+        None => revset_from_env(from_env),
+    };
+    if let Some(revset) = revset {
+        config.revset = Some(revset);
+        config.since_remote = None;
+    }
+}
+
+/// Runs `jj log` to evaluate the candidate `revset` (by default
+/// [`DEFAULT_REVSET`]) and return full hex commit IDs. Returns an empty vec on
+/// any failure (not a jj repo, bad revset, etc.).
+///
+/// This stays a subprocess on purpose. `jj log` snapshots the working copy
+/// first, so `@` includes the edits that triggered the hook; jj-lib has no
+/// snapshot entry point short of jj-cli's workspace command helper. The
+/// user's `revset-aliases` could be read from the `StackedConfig` into a
+/// `RevsetAliasesMap`, but the built-in ones the default revset and
+/// `mutable()` rely on (`trunk()`, `immutable_heads()`) are defined in
+/// jj-cli's default config, not jj-lib's. Everything after candidate
+/// selection is in-process.
+fn gather_candidates(runner: &dyn CommandRunner, revset: &str) -> Vec<String> {
+    try_gather_candidates(runner, revset).unwrap_or_default()
+}
+
+/// Like [`gather_candidates`], but surfaces the failure instead of failing
+/// open. Used by `--doctor` to report invalid revsets.
+fn try_gather_candidates(runner: &dyn CommandRunner, revset: &str) -> Result<Vec<String>> {
+    log_commit_ids(runner, &candidate_revset(revset))
+}
+
+/// `revset` narrowed to commits that can be redescribed, so a revset that
+/// reaches into trunk or the root commit doesn't flag history. jj's
+/// `mutable()` follows the user's `immutable_heads()`.
+fn candidate_revset(revset: &str) -> String {
+    format!("({revset}) & mutable()")
+}
+
+/// Keeps the first `cap` candidates (those nearest `@` in `jj log` order) so
+/// a runaway revset can't stall the hook. Returns a warning if any were
+/// dropped.
+fn cap_candidates(candidates: &mut Vec<String>, cap: usize) -> Option<String> {
+    if candidates.len() <= cap {
+        return None;
+    }
+    let total = candidates.len();
+    candidates.truncate(cap);
+    Some(format!(
+        "{total} candidates exceed max_candidates; checking only the first {cap}"
+    ))
+}
+
+/// `--print-candidates` output: the revset passed to `jj log`, then one
+/// candidate commit id per line (or the evaluation error).
+fn format_candidates(runner: &dyn CommandRunner, revset: &str) -> String {
+    let mut out = format!("revset: {}\n", candidate_revset(revset));
+    match try_gather_candidates(runner, revset) {
+        Ok(ids) => {
+            for id in ids {
+                out.push_str(&id);
+                out.push('\n');
+            }
+        }
+        Err(e) => out.push_str(&format!("error: {e:#}\n")),
+    }
+    out
+}
+
+/// Resolves `--rev` to the commit ids to check.
+///
+/// Change ids (and unique prefixes) are valid revsets, except when the change
+/// is divergent: then symbol resolution fails, so we list its commits with
+/// `change_id()` and give the verdict for the one reachable from `@` (or
+/// the newest, if none is), adding a note naming the others. Like the default
+/// revset, `--rev` is narrowed to mutable commits, so naming trunk checks
+/// nothing.
+fn resolve_rev(
+    runner: &dyn CommandRunner,
+    rev: &str,
+    notes: &mut Vec<String>,
+) -> Result<Vec<String>> {
+    let err = match try_gather_candidates(runner, rev) {
+        Ok(ids) => return Ok(ids),
+        Err(e) => e,
+    };
+    if !is_change_id_prefix(rev) {
+        return Err(err);
+    }
+    let Ok(all) = try_gather_candidates(runner, &format!("change_id({rev})")) else {
+        return Err(err);
+    };
+    if all.len() <= 1 {
+        return Ok(all);
+    }
+
+    let reachable = try_gather_candidates(runner, &format!("change_id({rev}) & ::@"))?;
+    let Some(picked) = reachable.first().or(all.first()).cloned() else {
+        return Ok(all);
+    };
+    notes.push(divergence_note(rev, &all, &picked));
+    Ok(vec![picked])
+}
+
+/// Whether `rev` could be a change id prefix: jj spells change ids with the
+/// letters `k`–`z`.
+fn is_change_id_prefix(rev: &str) -> bool {
+    !rev.is_empty() && rev.bytes().all(|b| (b'k'..=b'z').contains(&b))
+}
+
+fn divergence_note(rev: &str, all: &[String], picked: &str) -> String {
+    format!(
+        "note: change {rev} is divergent ({} commits: {}); checking {picked}",
+        all.len(),
+        all.join(", ")
+    )
+}
+
+/// Explains an empty candidate set, for debug output.
+///
+/// The common surprising case is `@` having been merged: once `trunk()` has
+/// advanced to or past `@`, `trunk()..@` is empty and nothing is checked.
+/// That's correct (merged changes are no longer in flight) but otherwise
+/// indistinguishable from "all descriptions are fine".
+fn explain_no_candidates(runner: &dyn CommandRunner, revset: &str) -> String {
+    match log_commit_ids(runner, "@ & ::trunk()") {
+        Ok(ids) if !ids.is_empty() => format!(
+            "no candidates: @ is trunk() or one of its ancestors, so \
+             `{revset}` is empty"
+        ),
+        Ok(_) => format!("no candidates: `{revset}` matched no changes"),
+        Err(e) => format!("no candidates: `{revset}` failed to evaluate ({e:#})"),
+    }
+}
+
+/// `jj log` of `@`'s commit ID as of the last operation, without snapshotting.
+const UNSNAPSHOTTED_WC_COMMIT_ARGS: [&str; 7] = [
+    "log",
+    "--ignore-working-copy",
+    "-r",
+    "@",
+    "--no-graph",
+    "-T",
+    "commit_id",
+];
+
+/// The same, after snapshotting the working copy.
+const SNAPSHOTTED_WC_COMMIT_ARGS: [&str; 6] = ["log", "-r", "@", "--no-graph", "-T", "commit_id"];
+
+/// With `require_clean_working_copy`, whether the working copy had edits
+/// not yet in `@`: snapshotting changes `@`'s commit ID. The snapshot
+/// records them, so the next run goes ahead unless editing continues.
+/// Errors don't defer.
+fn defer_for_pending_edits(runner: &dyn CommandRunner, config: &Config) -> bool {
+    if !config.require_clean_working_copy {
+        return false;
+    }
+    let before = runner.run_jj(&UNSNAPSHOTTED_WC_COMMIT_ARGS);
+    let after = runner.run_jj(&SNAPSHOTTED_WC_COMMIT_ARGS);
+    match (before, after) {
+        (Ok(before), Ok(after)) => before.trim() != after.trim(),
+        (Err(e), _) | (_, Err(e)) => {
+            debug!("working copy check failed ({e:#}); checking anyway");
+            false
+        }
+    }
+}
+
+/// Points the candidate revset at everything since `since_remote`, if set
+/// and the remote bookmark exists. Otherwise leaves it alone, saying why
+/// under debug.
+fn apply_since_remote(runner: &dyn CommandRunner, config: &mut Config) {
+    let Some(remote) = &config.since_remote else {
+        return;
+    };
+    match log_commit_ids(runner, remote) {
+        Ok(ids) if !ids.is_empty() => {
+            config.revset = Some(format!("{remote}..@ ~ empty()"));
+        }
+        Ok(_) => debug!(
+            "since_remote `{remote}` matches no commits; using revset `{}`",
+            config.revset()
+        ),
+        Err(e) => debug!(
+            "since_remote `{remote}` not found ({e:#}); using revset `{}`",
+            config.revset()
+        ),
+    }
+}
+
+/// Evaluates `revset` with `jj log` and returns the full hex commit IDs.
+fn log_commit_ids(runner: &dyn CommandRunner, revset: &str) -> Result<Vec<String>> {
+    let stdout = runner.run_jj(&[
+        "log",
+        "-r",
+        revset,
+        "--no-graph",
+        "-T",
+        r#"commit_id ++ "\n""#,
+    ])?;
+
+    Ok(stdout
+        .lines()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn parse_commit_ids(hexes: &[String]) -> Result<Vec<CommitId>> {
+    hexes
+        .iter()
+        .map(|hex| {
+            CommitId::try_from_hex(hex.as_bytes())
+                .with_context(|| format!("invalid commit id hex: {hex}"))
+        })
+        .collect()
+}
+
+/// Drops candidates the store can't find. A concurrent operation between
+/// gathering candidates and loading the repo can leave a commit unreachable;
+/// that shouldn't abort checking the rest (fail open per commit).
+fn retain_existing_commits(repo: &ReadonlyRepo, commit_ids: Vec<CommitId>) -> Vec<CommitId> {
+    commit_ids
+        .into_iter()
+        .filter(|commit_id| match repo.store().get_commit(commit_id) {
+            Ok(_) => true,
+            Err(err) => {
+                debug!("skipping commit {}: {err}", commit_id.hex());
+                false
+            }
+        })
+        .collect()
+}
+
+/// Resolves `config.fingerprint_base` to commit IDs. Falls back to
+/// parent-relative diffs (fail open) if the revset doesn't evaluate.
+fn resolve_fingerprint_base(runner: &dyn CommandRunner, config: &Config) -> FingerprintBase {
+    let Some(revset) = &config.fingerprint_base else {
+        return FingerprintBase::Parent;
+    };
+    match log_commit_ids(runner, revset).and_then(|hexes| parse_commit_ids(&hexes)) {
+        Ok(ids) => FingerprintBase::ForkPoint(ids),
+        Err(e) => {
+            debug!("fingerprint_base `{revset}` failed ({e:#}); diffing against parents");
+            FingerprintBase::Parent
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// jj-lib repo loading
+// ---------------------------------------------------------------------------
+
+/// Context for a [`RepoLoader`] failure. An unknown store type means the
+/// repo was written by a newer jj than the linked jj-lib, which upgrading
+/// the plugin fixes; say so rather than failing generically.
+fn repo_loader_error_context(e: &StoreLoadError) -> String {
+    match e {
+        StoreLoadError::UnsupportedType { .. } => format!(
+            "repo format newer than supported (plugin is built against jj-lib \
+             {JJ_LIB_VERSION}); upgrade the plugin"
+        ),
+        _ => "failed to init repo loader".to_owned(),
+    }
+}
+
+/// Loads the repo at HEAD, initializing a `RepoLoader` from `repo_path`
+/// (see [`RunContext::repo_path`]).
+fn load_repo(repo_path: &Path, settings: &UserSettings) -> Result<Arc<ReadonlyRepo>> {
+    // With jj-lib's `git` feature (see Cargo.toml), the defaults include the
+    // Git backend, so colocated repos load too.
+    let store_factories = StoreFactories::default();
+
+    let loader =
+        RepoLoader::init_from_file_system(settings, repo_path, &store_factories).map_err(|e| {
+            let context = repo_loader_error_context(&e);
+            anyhow::Error::new(e).context(context)
+        })?;
+    let repo = loader
+        .load_at_head()
+        .context("failed to load repo at head")?;
+
+    Ok(repo)
+}
+
+/// Loads jj's own config (jj-lib defaults, then user, then repo config) so
+/// that repo loading and our `[active-descriptions]` namespace see the same
+/// settings `jj` does. Unreadable or malformed layers are skipped (fail open).
+fn load_user_settings(repo_path: &Path) -> Result<UserSettings> {
+    let mut config = StackedConfig::with_defaults();
+    let layers = user_config_paths()
+        .into_iter()
+        .map(|path| (ConfigSource::User, path))
+        .chain([(ConfigSource::Repo, repo_path.join("config.toml"))]);
+    for (source, path) in layers {
+        let loaded = if path.is_dir() {
+            config.load_dir(source, &path)
+        } else if path.is_file() {
+            config.load_file(source, path.clone())
+        } else {
+            continue;
+        };
+        if let Err(e) = loaded {
+            debug!("skipping jj config {}: {e}", path.display());
+        }
+    }
+    UserSettings::from_config(config).context("failed to create UserSettings")
+}
+
+/// jj's user config files and directories, mirroring the CLI's lookup:
+/// `$JJ_CONFIG` if set, otherwise `~/.jjconfig.toml` plus `jj/config.toml`
+/// and `jj/conf.d/` under the XDG config dir.
+fn user_config_paths() -> Vec<PathBuf> {
+    if let Some(paths) = env::var_os("JJ_CONFIG") {
+        return env::split_paths(&paths)
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect();
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    let mut paths = Vec::new();
+    if let Some(home) = &home {
+        paths.push(home.join(".jjconfig.toml"));
+    }
+    if let Some(config_dir) = config_dir {
+        paths.push(config_dir.join("jj").join("config.toml"));
+        paths.push(config_dir.join("jj").join("conf.d"));
+    }
+    paths
+}
+
+/// Resolves the repo path, following jj's workspace indirection.
+///
+/// In secondary workspaces, `.jj/repo` is a file containing the path to the
+/// primary workspace's repo directory rather than a directory itself.
+fn resolve_repo_path(path: &Path) -> Result<PathBuf> {
+    if path.is_file() {
+        let target = fs::read_to_string(path)
+            .with_context(|| format!("failed to read repo pointer at {}", path.display()))?;
+        let target = target.trim();
+        Ok(PathBuf::from(target))
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// The workspace root cached in `cache` for `cwd` by an earlier run, if it
+/// was written less than [`WORKSPACE_ROOT_CACHE_TTL`] before `now` and the
+/// root still exists.
+fn read_cached_root(cache: &Path, cwd: &Path, now: SystemTime) -> Option<PathBuf> {
+    let text = fs::read_to_string(cache).ok()?;
+    let mut lines = text.lines();
+    let (cached_cwd, root, written) = (lines.next()?, lines.next()?, lines.next()?);
+    let written = UNIX_EPOCH + Duration::from_secs(written.parse().ok()?);
+    let fresh = now
+        .duration_since(written)
+        .is_ok_and(|age| age < WORKSPACE_ROOT_CACHE_TTL);
+    let root = PathBuf::from(root);
+    (fresh && Path::new(cached_cwd) == cwd && root.is_dir()).then_some(root)
+}
+
+/// Caches `root` as `cwd`'s workspace root for [`read_cached_root`]. Write
+/// errors only cost the next run a `jj root`.
+fn write_cached_root(cache: &Path, cwd: &Path, root: &Path, now: SystemTime) {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |age| age.as_secs());
+    let text = format!("{}\n{}\n{secs}\n", cwd.display(), root.display());
+    if let Err(e) = fs::write(cache, text) {
+        debug!("failed to cache workspace root: {e}");
+    }
+}
+
+/// Gets the workspace root by running `jj root`.
+///
+/// The root is canonicalized so symlinked locations (e.g. macOS's `/var` →
+/// `/private/var`) agree with the on-disk `.jj`. If that fails, the path is
+/// used as reported.
+fn discover_workspace_root(runner: &dyn CommandRunner) -> Result<PathBuf> {
+    let root = runner
+        .run_jj(&["root"])
+        .context("not a jj repo (jj root failed)")?;
+    let root = PathBuf::from(root.trim());
+
+    Ok(fs::canonicalize(&root).unwrap_or_else(|e| {
+        debug!(
+            "failed to canonicalize {}: {e}; using it as is",
+            root.display()
+        );
+        root
+    }))
+}
+
+/// Loads the per-repo config with `overrides` on top, falling back to
+/// defaults (fail open) when it can't be read or parsed.
+fn load_config(
+    workspace_root: &Path,
+    jj_config: &StackedConfig,
+    overrides: &toml::Table,
+) -> Config {
+    Config::load_with_overrides(workspace_root, jj_config, overrides).unwrap_or_else(|e| {
+        debug!("{e:#}; using default config");
+        Config::default()
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Candidate checking
+// ---------------------------------------------------------------------------
+
+/// Number of worker threads for [`check_verdicts`] when not configured.
+fn default_thread_count() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Runs [`check_staleness`] for every candidate on up to `threads` worker
+/// threads and returns the verdicts in candidate (`jj log`) order.
+fn check_verdicts(
+    repo: &ReadonlyRepo,
+    commit_ids: &[CommitId],
+    options: &CheckOptions,
+    threads: usize,
+) -> Result<Vec<Verdict>> {
+    map_in_order(commit_ids, threads, |commit_id| {
+        check_staleness(repo, commit_id, options)
+    })
+    .into_iter()
+    .collect()
+}
+
+/// `--verbose` output: why each clean candidate wasn't flagged.
+fn format_clean_verdicts(commit_ids: &[CommitId], verdicts: &[Verdict]) -> String {
+    commit_ids
+        .iter()
+        .zip(verdicts)
+        .filter_map(|(commit_id, verdict)| match verdict {
+            Verdict::Clean(reason) => {
+                let hex = commit_id.hex();
+                Some(format!(
+                    "clean: commit {}: {reason}\n",
+                    &hex[..hex.len().min(12)]
+                ))
+            }
+            Verdict::Stale(_) => None,
+        })
+        .collect()
+}
+
+/// With `stack_positions`, where each stale change sits on the stack from
+/// the fingerprint base (or `trunk()`) up to `@`, keyed by short change ID.
+/// Resolution failures are logged under debug and leave positions out.
+fn stale_stack_positions(
+    runner: &dyn CommandRunner,
+    repo: &ReadonlyRepo,
+    options: &CheckOptions,
+    commit_ids: &[CommitId],
+    verdicts: &[Verdict],
+) -> Vec<(String, report::StackPosition)> {
+    let resolve = || -> Result<Vec<(String, report::StackPosition)>> {
+        let base_ids = match &options.base {
+            FingerprintBase::ForkPoint(ids) => ids.clone(),
+            FingerprintBase::Parent => parse_commit_ids(&log_commit_ids(runner, "trunk()")?)?,
+        };
+        let head_id = parse_commit_ids(&log_commit_ids(runner, "@")?)?
+            .into_iter()
+            .next()
+            .context("`@` resolved to no commit")?;
+        let stale_ids: Vec<CommitId> = commit_ids
             .iter()
-            .map(|(p, c)| {
-                (
-                    jj_lib::repo_path::RepoPath::from_internal_string(p)
-                        .expect("valid path"),
-                    *c,
-                )
+            .zip(verdicts)
+            .filter(|(_, verdict)| matches!(verdict, Verdict::Stale(_)))
+            .map(|(commit_id, _)| commit_id.clone())
+            .collect();
+        let positions = stack_positions(repo, &base_ids, &head_id, &stale_ids)?;
+        stale_ids
+            .iter()
+            .filter_map(|commit_id| Some((commit_id, *positions.get(commit_id)?)))
+            .map(|(commit_id, position)| {
+                let commit = repo.store().get_commit(commit_id)?;
+                Ok((change_id_prefix(&commit, options.change_id_len()), position))
+            })
+            .collect()
+    };
+    resolve().unwrap_or_else(|e| {
+        debug!("stack positions unavailable ({e:#})");
+        Vec::new()
+    })
+}
+
+/// The 1-based position of each of `commit_ids` on the stack
+/// `base_ids..head_id`, and that stack's size. With branching history only
+/// the ancestors of `head_id` count; commits off that path are left out.
+fn stack_positions(
+    repo: &ReadonlyRepo,
+    base_ids: &[CommitId],
+    head_id: &CommitId,
+    commit_ids: &[CommitId],
+) -> Result<HashMap<CommitId, report::StackPosition>> {
+    let base = ResolvedRevsetExpression::commits(base_ids.to_vec()).ancestors();
+    // `base_ids..id`.
+    let stack_below = |id: &CommitId| -> Result<Vec<CommitId>> {
+        let range = ResolvedRevsetExpression::commits(vec![id.clone()])
+            .ancestors()
+            .minus(&base);
+        let ids = range
+            .evaluate(repo)?
+            .iter()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
+    };
+    let stack: HashSet<CommitId> = stack_below(head_id)?.into_iter().collect();
+
+    let mut positions = HashMap::new();
+    for commit_id in commit_ids {
+        if stack.contains(commit_id) {
+            let position = report::StackPosition {
+                position: stack_below(commit_id)?.len(),
+                size: stack.len(),
+            };
+            positions.insert(commit_id.clone(), position);
+        }
+    }
+    Ok(positions)
+}
+
+/// `--format ndjson` records: one per candidate, clean or stale, with
+/// `change_id_len`-character change IDs.
+fn change_records(
+    repo: &ReadonlyRepo,
+    commit_ids: &[CommitId],
+    verdicts: &[Verdict],
+    change_id_len: usize,
+) -> Result<Vec<report::ChangeRecord>> {
+    commit_ids
+        .iter()
+        .zip(verdicts)
+        .map(|(commit_id, verdict)| {
+            let commit = repo.store().get_commit(commit_id)?;
+            Ok(report::ChangeRecord::new(
+                change_id_prefix(&commit, change_id_len),
+                commit_id,
+                verdict,
+            ))
+        })
+        .collect()
+}
+
+/// Applies `f` to every item on up to `threads` worker threads, returning
+/// the results in item order. With one thread (or one item) no threads are
+/// spawned and items are processed sequentially on the caller's thread.
+///
+/// Workers pull items from a shared counter, so they finish in scheduling
+/// order. Each result is tagged with its item index and re-sorted before
+/// returning, which keeps output order independent of thread timing.
+fn map_in_order<T: Sync, R: Send>(
+    items: &[T],
+    threads: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let threads = threads.clamp(1, items.len().max(1));
+    if threads == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = &AtomicUsize::new(0);
+    let f = &f;
+    let mut indexed: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(move || {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break;
+                        };
+                        results.push((index, f(item)));
+                    }
+                    results
+                })
             })
             .collect();
-        create_tree(repo, &path_contents)
+
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+
+    indexed.sort_by_key(|(index, _)| *index);
+    indexed.into_iter().map(|(_, result)| result).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Staleness detection
+// ---------------------------------------------------------------------------
+
+/// Inputs to [`check_staleness`] beyond the commit itself: the user's config
+/// plus anything resolved from the repo once per run.
+#[derive(Debug, Default)]
+struct CheckOptions {
+    config: Config,
+    /// What each diff fingerprint is taken against.
+    base: FingerprintBase,
+    /// The current time in milliseconds since the epoch, for
+    /// `empty_grace_period_secs`.
+    now_millis: i64,
+    /// Directory of cached fingerprint digests, with `fingerprint_cache`.
+    fingerprint_cache: Option<PathBuf>,
+    /// jj's `user.email`, for `only_mine`. `None` when unset, which checks
+    /// every change.
+    user_email: Option<String>,
+    /// Characters of each change ID reported, from
+    /// [`unambiguous_change_id_len`]. `None` uses `change_id_length`.
+    change_id_len: Option<usize>,
+    /// `--changed-only`: only content drift counts, so the description
+    /// checks are skipped.
+    changed_only: bool,
+}
+
+impl CheckOptions {
+    fn change_id_len(&self) -> usize {
+        self.change_id_len
+            .unwrap_or_else(|| self.config.change_id_length())
+    }
+}
+
+/// Whether `timestamp` is less than `secs` seconds before `now_millis`.
+/// Always false for `0`.
+fn younger_than(timestamp: &Timestamp, now_millis: i64, secs: u64) -> bool {
+    let grace_millis = i64::try_from(secs.saturating_mul(1000)).unwrap_or(i64::MAX);
+    secs > 0 && now_millis - timestamp.timestamp.0 < grace_millis
+}
+
+/// Milliseconds since the epoch, per the system clock.
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX)
+        })
+}
+
+impl From<Config> for CheckOptions {
+    fn from(config: Config) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+}
+
+/// What a commit's diff fingerprint is computed against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+enum FingerprintBase {
+    /// The commit's (auto-merged) parents: the change's own content.
+    #[default]
+    Parent,
+    /// The commit's fork point with these base commits (e.g. `trunk()`):
+    /// the net content of the whole stack up to and including the change.
+    ForkPoint(Vec<CommitId>),
+}
+
+/// Checks whether a commit's description is stale relative to its content.
+///
+/// Returns [`Verdict::Clean`] with the reason the description is current, or
+/// [`Verdict::Stale`] with the change ID and list of files whose
+/// diff-from-parent changed since the description was last set.
+///
+/// A description is stale if:
+/// - The commit has a non-empty diff but an empty description (unless
+///   `config.skip_empty_check` is set, in which case undescribed changes are
+///   never stale, or the change was authored within
+///   `config.empty_grace_period_secs`), OR
+/// - The commit's diff-from-parent has changed since the description was last
+///   set (determined by walking the evolution log and comparing tree diffs).
+///
+/// This compares actual diffs rather than using heuristics about tree/parent
+/// change ordering, which avoids false positives from splits, squashes, and
+/// rebases that alter the tree without changing the logical content.
+///
+/// Descriptions matching `config.always_valid_descriptions` are never stale,
+/// unless the change touches a [`FileClass::Sensitive`] file. Files
+/// classified [`FileClass::Ignore`] are left out of every diff.
+fn check_staleness(
+    repo: &ReadonlyRepo,
+    commit_id: &CommitId,
+    options: &CheckOptions,
+) -> Result<Verdict> {
+    // The root commit has no parent or evolog to compare; `mutable()` keeps
+    // immutable commits out of the candidates already.
+    if commit_id == repo.store().root_commit_id() {
+        return Ok(Verdict::Clean(CleanReason::Immutable));
+    }
+    let commit = repo.store().get_commit(commit_id)?;
+    if options.config.skip_merges && commit.parent_ids().len() > 1 {
+        return Ok(Verdict::Clean(CleanReason::MergeSkipped));
+    }
+    if options.config.only_mine
+        && let Some(email) = &options.user_email
+        && !commit.author().email.eq_ignore_ascii_case(email)
+    {
+        return Ok(Verdict::Clean(CleanReason::NotMine));
+    }
+
+    // The change's own diff, and against `options.base`, each computed at
+    // most once for the rules that need them.
+    let own_cell = OnceCell::new();
+    let own_diff = || {
+        get_or_try_init(&own_cell, || {
+            cached_fingerprint(repo, &commit, &FingerprintBase::Parent, options)
+        })
+    };
+    let base_cell = OnceCell::new();
+    let current_diff = || match options.base {
+        FingerprintBase::Parent => own_diff(),
+        FingerprintBase::ForkPoint(_) => get_or_try_init(&base_cell, || {
+            cached_fingerprint(repo, &commit, &options.base, options)
+        }),
+    };
+
+    // Changes touching sensitive files can't be exempted.
+    let exemptible = !options.config.has_class(FileClass::Sensitive)
+        || !touches_sensitive_file(own_diff()?, &options.config);
+    if exemptible && options.config.is_always_valid(commit.description()) {
+        return Ok(Verdict::Clean(CleanReason::AlwaysValid));
+    }
+    if exemptible
+        && younger_than(
+            &commit.committer().timestamp,
+            options.now_millis,
+            options.config.grace_seconds,
+        )
+    {
+        return Ok(Verdict::Clean(CleanReason::RecentlyEdited));
+    }
+
+    let change_id_short = change_id_prefix(&commit, options.change_id_len());
+    let description = options
+        .config
+        .include_description_chars
+        .map(|max_chars| truncate_description(commit.description(), max_chars));
+
+    // With `--changed-only` only drift counts, so none of the description
+    // checks below apply.
+    let lint = !options.changed_only;
+
+    // Empty description on a non-empty change is always stale.
+    // Report every file in the current diff as changed, unless configured to
+    // just say it's undescribed.
+    if lint && commit.description().is_empty() {
+        if exemptible && options.config.skip_empty_check {
+            return Ok(Verdict::Clean(CleanReason::EmptyCheckSkipped));
+        }
+        if exemptible
+            && younger_than(
+                &commit.author().timestamp,
+                options.now_millis,
+                options.config.empty_grace_period_secs,
+            )
+        {
+            return Ok(Verdict::Clean(CleanReason::WithinGracePeriod));
+        }
+        let changed_files: Vec<RepoPathBuf> = if options.config.omit_files_for_empty {
+            Vec::new()
+        } else {
+            current_diff()?.keys().cloned().collect()
+        };
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::EmptyDescription,
+            changed_files,
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    let min_chars = options.config.min_description_chars;
+    if lint && commit.description().trim().chars().count() < min_chars {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::DescriptionTooShort,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: Some(min_chars.to_string()),
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint && let Some(placeholder) = options.config.placeholder_in(commit.description()) {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::PlaceholderDescription,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: Some(placeholder.to_owned()),
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint && let Some(phrase) = options.config.banned_phrase_in(commit.description()) {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::BannedPhrase,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: Some(phrase.to_owned()),
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint
+        && let Some(pattern) = options
+            .config
+            .subject_pattern_mismatch(commit.description())
+    {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::PatternMismatch,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: Some(pattern.to_owned()),
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint
+        && options
+            .config
+            .breaks_conventional_format(commit.description())
+    {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::NonConventionalFormat,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint
+        && let Some(min_words) = options.config.min_subject_words
+        && meaningful_subject_words(commit.description()) < min_words
+    {
+        return Ok(Verdict::Stale(StalenessInfo {
+            change_id_short,
+            reason: StaleReason::SubjectTooThin,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }));
+    }
+
+    if lint
+        && let Some(max_files) = options.config.require_body_over_files
+        && !has_body(commit.description())
+    {
+        let touched = own_diff()?;
+        if touched.len() > max_files {
+            return Ok(Verdict::Stale(StalenessInfo {
+                change_id_short,
+                reason: StaleReason::MissingBody,
+                changed_files: touched.keys().cloned().collect(),
+                confidence: Confidence::High,
+                described_commit_id: None,
+                description,
+                detail: None,
+                line_counts: BTreeMap::new(),
+            }));
+        }
+    }
+
+    if lint && options.config.flag_filename_restatements {
+        let touched: Vec<RepoPathBuf> = own_diff()?.keys().cloned().collect();
+        if restates_filenames(commit.description(), &touched) {
+            return Ok(Verdict::Stale(StalenessInfo {
+                change_id_short,
+                reason: StaleReason::RestatesFilename,
+                changed_files: touched,
+                confidence: Confidence::High,
+                described_commit_id: None,
+                description,
+                detail: None,
+                line_counts: BTreeMap::new(),
+            }));
+        }
+    }
+
+    if lint && options.config.flag_mismatched_descriptions {
+        let touched: Vec<RepoPathBuf> = own_diff()?.keys().cloned().collect();
+        let references = referenced_paths(commit.description());
+        if let Some(sibling) = mismatched_sibling(repo, &commit, &references, &touched, options)? {
+            return Ok(Verdict::Stale(StalenessInfo {
+                change_id_short,
+                reason: StaleReason::DescriptionMismatchesDiff,
+                changed_files: touched,
+                confidence: Confidence::High,
+                described_commit_id: None,
+                description,
+                detail: Some(sibling),
+                line_counts: BTreeMap::new(),
+            }));
+        }
+    }
+
+    let entries = evolog_to_describe_point(repo, commit_id, &options.config)?;
+    trace!("{change_id_short}: {} evolog entries loaded", entries.len());
+    if entries.len() < 2 {
+        // Single entry (freshly created) — if it has a description, it's fine.
+        return Ok(Verdict::Clean(CleanReason::DescribedAtCreation));
+    }
+
+    // If the description was never changed, it was established at the first
+    // evolog entry. We still need to compare its diff to the current diff to
+    // catch content edits that happened after the initial describe.
+    let (index, confidence) = describe_point(&entries, &options.config);
+    let described_commit = &entries[index];
+    trace!(
+        "{change_id_short}: describe point is entry {index} ({}, {confidence:?})",
+        described_commit.id().hex()
+    );
+
+    // Compare the diff-from-parent at describe-time vs now. If identical,
+    // the logical content hasn't changed and the description is still valid.
+    let described_diff = cached_fingerprint(repo, described_commit, &options.base, options)?;
+    let current_diff = current_diff()?;
+
+    if described_diff == *current_diff {
+        trace!("{change_id_short}: fingerprints match");
+        return Ok(Verdict::Clean(CleanReason::UnchangedSinceDescribe));
+    }
+    let mut drifted = drifted_paths(repo, &described_diff, current_diff, &options.config)?;
+    // Any drift in a sensitive file counts, whitespace-only or however small.
+    let sensitive: Vec<RepoPathBuf> = if options.config.has_class(FileClass::Sensitive) {
+        diff_fingerprint_changes(&described_diff, current_diff)
+            .into_iter()
+            .filter(|path| options.config.classify(path) == FileClass::Sensitive)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for path in &sensitive {
+        if !drifted.contains(path) {
+            drifted.push(path.clone());
+        }
+    }
+    drifted.sort();
+    trace!(
+        "{change_id_short}: fingerprints differ in {}",
+        if drifted.is_empty() {
+            "nothing that counts".to_owned()
+        } else {
+            drifted
+                .iter()
+                .map(|path| path.as_internal_file_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    );
+    if drifted.is_empty() {
+        return Ok(Verdict::Clean(CleanReason::UnchangedSinceDescribe));
+    }
+    let dropped = options
+        .config
+        .flag_missing_references
+        .then(|| dropped_reference(commit.description(), &described_diff, current_diff))
+        .flatten();
+    let min_lines = options.config.min_changed_lines;
+    if min_lines > 0
+        && dropped.is_none()
+        && sensitive.is_empty()
+        && lines_changed_since(repo, &described_diff, current_diff, &drifted)?
+            .is_some_and(|lines| lines < min_lines)
+    {
+        return Ok(Verdict::Clean(CleanReason::BelowLineThreshold));
+    }
+
+    let changed_files = if options.config.net_changed_files {
+        current_diff.keys().cloned().collect()
+    } else {
+        drifted
+    };
+    let line_counts = if options.config.line_counts {
+        line_counts(repo, current_diff, &changed_files)?
+    } else {
+        BTreeMap::new()
+    };
+
+    let reason = match dropped {
+        Some(_) => StaleReason::ReferencesMissingFile,
+        None => StaleReason::ContentChanged,
+    };
+
+    Ok(Verdict::Stale(StalenessInfo {
+        change_id_short,
+        reason,
+        changed_files,
+        confidence,
+        described_commit_id: Some(described_commit.id().clone()),
+        description,
+        detail: dropped,
+        line_counts,
+    }))
+}
+
+/// [`OnceCell::get_or_init`] for a fallible `init`.
+fn get_or_try_init<T>(cell: &OnceCell<T>, init: impl FnOnce() -> Result<T>) -> Result<&T> {
+    if let Some(value) = cell.get() {
+        return Ok(value);
+    }
+    let value = init()?;
+    Ok(cell.get_or_init(|| value))
+}
+
+/// The user-facing change ID, shortened to `len` characters.
+fn change_id_prefix(commit: &Commit, len: usize) -> String {
+    // ChangeId::Display uses reverse_hex (the user-facing jj format).
+    let full_change_id = commit.change_id().to_string();
+    full_change_id[..full_change_id.len().min(len)].to_owned()
+}
+
+/// The shortest prefix length, at least `min_len`, that tells every pair of
+/// distinct `change_ids` apart. Divergent commits share a change ID, so
+/// identical IDs don't count.
+fn unambiguous_change_id_len(change_ids: &[String], min_len: usize) -> usize {
+    let mut sorted: Vec<&str> = change_ids.iter().map(String::as_str).collect();
+    sorted.sort_unstable();
+    sorted.dedup();
+    sorted
+        .windows(2)
+        .map(|pair| {
+            let shared = pair[0]
+                .bytes()
+                .zip(pair[1].bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            shared + 1
+        })
+        .fold(min_len.max(1), usize::max)
+}
+
+/// Short change IDs of the candidates, in candidate order, for reports that
+/// list clean changes too.
+fn checked_change_ids(
+    repo: &ReadonlyRepo,
+    commit_ids: &[CommitId],
+    len: usize,
+) -> Result<Vec<String>> {
+    let mut change_ids = commit_ids
+        .iter()
+        .map(|id| Ok(change_id_prefix(&repo.store().get_commit(id)?, len)))
+        .collect::<Result<Vec<_>>>()?;
+    change_ids.dedup();
+    Ok(change_ids)
+}
+
+/// Full change IDs of the candidates, keyed by their short form, so state
+/// that outlives a run doesn't depend on the short ID length.
+fn full_change_ids(
+    repo: &ReadonlyRepo,
+    commit_ids: &[CommitId],
+    len: usize,
+) -> Result<HashMap<String, String>> {
+    commit_ids
+        .iter()
+        .map(|id| {
+            let commit = repo.store().get_commit(id)?;
+            Ok((
+                change_id_prefix(&commit, len),
+                commit.change_id().to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the commit's evolution log, oldest first, capped at the newest
+/// `max_entries` entries.
+fn evolog_commits(
+    repo: &ReadonlyRepo,
+    commit_id: &CommitId,
+    max_entries: usize,
+) -> Result<Vec<Commit>> {
+    let mut entries = Vec::new();
+    for result in walk_predecessors(repo, std::slice::from_ref(commit_id)) {
+        let entry = result.context("evolog walk failed")?;
+        entries.push(entry.commit);
+        if entries.len() >= max_entries {
+            break;
+        }
+    }
+
+    // walk_predecessors yields newest-first; reverse for chronological.
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Like [`evolog_commits`], but with the `last-description-change` describe
+/// point, stops walking once the description changes: everything older
+/// than that can't move the describe point. [`describe_point`] of the
+/// result picks the same commit as of the whole evolog.
+fn evolog_to_describe_point(
+    repo: &ReadonlyRepo,
+    commit_id: &CommitId,
+    config: &Config,
+) -> Result<Vec<Commit>> {
+    if config.describe_point != DescribePointStrategy::LastDescriptionChange {
+        return evolog_commits(repo, commit_id, config.max_evolog_entries());
+    }
+    let mut entries: Vec<Commit> = Vec::new();
+    let mut newest_description = None;
+    for result in walk_predecessors(repo, std::slice::from_ref(commit_id)) {
+        let commit = result.context("evolog walk failed")?.commit;
+        let description = comparable_description(commit.description(), config);
+        let changed = newest_description
+            .as_ref()
+            .is_some_and(|newest| *newest != description);
+        newest_description.get_or_insert(description);
+        entries.push(commit);
+        if changed || entries.len() >= config.max_evolog_entries() {
+            break;
+        }
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Index into `entries` (non-empty, oldest first) of the entry the current
+/// diff is compared against, per `describe_point`, and how sure that is.
+fn describe_point(entries: &[Commit], config: &Config) -> (usize, Confidence) {
+    match config.describe_point {
+        DescribePointStrategy::LastDescriptionChange => {
+            match last_describe_index(entries, config) {
+                Some(index) => (index, Confidence::High),
+                None => (0, Confidence::Low),
+            }
+        }
+        DescribePointStrategy::FirstEntry => (0, Confidence::High),
+    }
+}
+
+/// Index into `entries` (oldest first) of the entry where the description
+/// was last changed, or `None` if it never was. Line-ending-only edits
+/// (CRLF vs LF) never count, and with `normalize_descriptions` and/or
+/// `ignore_markdown`, reformat-only edits don't either.
+///
+/// The description is constant after the last change, so this is always
+/// the transition into the current description: describing, clearing and
+/// re-describing anchors on the re-describe, not the first describe.
+fn last_describe_index(entries: &[Commit], config: &Config) -> Option<usize> {
+    let normalize = |description| comparable_description(description, config);
+    (1..entries.len())
+        .rev()
+        .find(|&i| normalize(entries[i].description()) != normalize(entries[i - 1].description()))
+}
+
+/// `description` as [`last_describe_index`] compares it: with line endings
+/// and, as configured, markdown and formatting normalized away.
+fn comparable_description(description: &str, config: &Config) -> String {
+    let description = description.replace("\r\n", "\n");
+    let description = if config.ignore_markdown {
+        strip_markdown(&description)
+    } else {
+        description
+    };
+    if config.normalize_descriptions {
+        normalize_description(&description)
+    } else {
+        description
+    }
+}
+
+/// Strips markdown syntax (headings, block quotes, list markers, emphasis and
+/// code marks) and collapses whitespace, so descriptions whose text reads the
+/// same however it's marked up compare equal.
+fn strip_markdown(description: &str) -> String {
+    description
+        .lines()
+        .map(|line| strip_list_marker(line.trim_start().trim_start_matches(['#', '>'])))
+        .flat_map(str::split_whitespace)
+        .map(|word| word.replace(['*', '_', '`'], ""))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Strips a leading `-`, `*`, `+`, `1.` or `1)` list marker from `line`.
+fn strip_list_marker(line: &str) -> &str {
+    let line = line.trim_start();
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = if digits > 0 {
+        line[digits..].strip_prefix(['.', ')'])
+    } else {
+        line.strip_prefix(['-', '*', '+'])
+    };
+    match rest {
+        Some(rest) if rest.starts_with(' ') => rest,
+        _ => line,
+    }
+}
+
+/// Folds case and collapses whitespace, so descriptions differing only in
+/// formatting compare equal.
+fn normalize_description(description: &str) -> String {
+    description
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Caps `description` at `max_chars` characters (plus an ellipsis when cut),
+/// without trailing whitespace.
+fn truncate_description(description: &str, max_chars: usize) -> String {
+    let description = description.trim_end();
+    match description.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &description[..end]),
+        None => description.to_owned(),
+    }
+}
+
+/// Words that don't count towards a description saying something, for
+/// [`restates_filenames`].
+const RESTATEMENT_FILLER_WORDS: &[&str] = &[
+    "a", "add", "added", "adds", "an", "and", "change", "changed", "changes", "edit", "edited",
+    "file", "files", "fix", "fixed", "fixes", "in", "modify", "modified", "the", "to", "tweak",
+    "update", "updated", "updates",
+];
+
+/// Whether a subject-only `description` says nothing beyond the names of
+/// `paths`: every word left after dropping a conventional-commit type and
+/// [`RESTATEMENT_FILLER_WORDS`] is a path, a path component, or a piece of
+/// one. Deliberately conservative: a body, any other word, or nothing left
+/// at all means it isn't flagged.
+fn restates_filenames(description: &str, paths: &[RepoPathBuf]) -> bool {
+    if has_body(description) {
+        return false;
+    }
+    let mut path_words = HashSet::new();
+    for path in paths {
+        let path = path.as_internal_file_string().to_lowercase();
+        for component in path.split('/') {
+            path_words.extend(component.split(['.', '-', '_']).map(str::to_owned));
+            path_words.insert(component.to_owned());
+        }
+        path_words.insert(path);
+    }
+
+    let subject = description.trim().to_lowercase();
+    let mut words = subject.split_whitespace().peekable();
+    // Skip a `type(scope):` prefix.
+    words.next_if(|word| word.ends_with(':'));
+    let mut meaningful = words
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty() && !RESTATEMENT_FILLER_WORDS.contains(word))
+        .peekable();
+    meaningful.peek().is_some() && meaningful.all(|word| path_words.contains(word))
+}
+
+/// Paths `description` mentions, like `src/main.rs` or `` `config.toml` ``:
+/// words made only of path characters with a `/` or a file extension, less
+/// surrounding quotes and punctuation. URLs and version numbers don't count.
+fn referenced_paths(description: &str) -> Vec<&str> {
+    description
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| "`'\"()[]{}<>,;:!?".contains(c))
+                .trim_end_matches('.')
+        })
+        .filter(|word| {
+            let is_path_char = |c: char| c.is_ascii_alphanumeric() || "._/-".contains(c);
+            let has_extension = word.rsplit_once('.').is_some_and(|(stem, ext)| {
+                !stem.is_empty() && ext.starts_with(char::is_alphabetic)
+            });
+            !word.contains("//")
+                && word.chars().all(is_path_char)
+                && (word.contains('/') || has_extension)
+        })
+        .collect()
+}
+
+/// Whether `reference` (from [`referenced_paths`]) names `path`: the whole
+/// path or a trailing run of its components.
+fn references_path(reference: &str, path: &RepoPath) -> bool {
+    let path = path.as_internal_file_string();
+    path == reference
+        || path
+            .strip_suffix(reference)
+            .is_some_and(|prefix| prefix.ends_with('/'))
+}
+
+/// The first path `description` names (per [`referenced_paths`]) that is in
+/// the `described` fingerprint but not `current`. Conservative: only paths
+/// the change really touched count, and not if the name still matches a
+/// path it touches, e.g. after a move.
+fn dropped_reference(
+    description: &str,
+    described: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    current: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+) -> Option<String> {
+    referenced_paths(description)
+        .into_iter()
+        .filter(|reference| !current.keys().any(|path| references_path(reference, path)))
+        .find_map(|reference| {
+            described
+                .keys()
+                .find(|path| references_path(reference, path))
+                .map(|path| path.as_internal_file_string().to_owned())
+        })
+}
+
+/// The short change ID of a sibling of `commit` (a child of its parents)
+/// whose own diff has every path in `references`, when `touched` has none
+/// of them. Conservative: a description naming no paths, or any path this
+/// change touches, is never a mismatch.
+fn mismatched_sibling(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    references: &[&str],
+    touched: &[RepoPathBuf],
+    options: &CheckOptions,
+) -> Result<Option<String>> {
+    let names_touched = |paths: &[RepoPathBuf]| {
+        references
+            .iter()
+            .any(|reference| paths.iter().any(|path| references_path(reference, path)))
+    };
+    if references.is_empty() || names_touched(touched) {
+        return Ok(None);
+    }
+    let siblings = ResolvedRevsetExpression::commits(commit.parent_ids().to_vec())
+        .children()
+        .minus(&ResolvedRevsetExpression::commits(vec![
+            commit.id().clone(),
+        ]));
+    for sibling_id in siblings.evaluate(repo)?.iter() {
+        let sibling = repo.store().get_commit(&sibling_id?)?;
+        let diff = cached_fingerprint(repo, &sibling, &FingerprintBase::Parent, options)?;
+        let paths: Vec<RepoPathBuf> = diff.into_keys().collect();
+        if references
+            .iter()
+            .all(|reference| paths.iter().any(|path| references_path(reference, path)))
+        {
+            return Ok(Some(change_id_prefix(&sibling, options.change_id_len())));
+        }
+    }
+    Ok(None)
+}
+
+/// Conventional-commit types, per the describe skill.
+const CONVENTIONAL_TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// Words that carry no meaning in a subject, for
+/// [`meaningful_subject_words`].
+const THIN_SUBJECT_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "for", "in", "misc", "of", "on", "or", "stuff", "the", "things", "tmp", "to",
+    "todo", "wip",
+];
+
+/// Counts the words in `description`'s subject that aren't
+/// [`THIN_SUBJECT_STOPWORDS`], after a `type(scope)!:` prefix using one of
+/// [`CONVENTIONAL_TYPES`].
+fn meaningful_subject_words(description: &str) -> usize {
+    let subject = description.lines().next().unwrap_or_default();
+    let subject = strip_conventional_prefix(subject).unwrap_or(subject);
+    subject
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty() && !THIN_SUBJECT_STOPWORDS.contains(&word.as_str()))
+        .count()
+}
+
+/// The rest of `subject` after a conventional-commit prefix, if it has one.
+fn strip_conventional_prefix(subject: &str) -> Option<&str> {
+    let (head, rest) = subject.split_once(':')?;
+    let head = head.strip_suffix('!').unwrap_or(head);
+    let kind = match head.split_once('(') {
+        Some((kind, scope)) => {
+            let scope = scope.strip_suffix(')')?;
+            if scope.is_empty() || scope.contains(['(', ')']) {
+                return None;
+            }
+            kind
+        }
+        None => head,
+    };
+    CONVENTIONAL_TYPES.contains(&kind).then_some(rest)
+}
+
+/// Whether `description` has a body: non-blank text after the subject line.
+fn has_body(description: &str) -> bool {
+    description
+        .split_once('\n')
+        .is_some_and(|(_, body)| !body.trim().is_empty())
+}
+
+/// [`commit_diff_fingerprint`] without the files `commit`'s `.gitignore`
+/// files ignore, nor empty files added or deleted when `ignore_empty_files`
+/// is set.
+fn classified_fingerprint(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    base: &FingerprintBase,
+    config: &Config,
+) -> Result<BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>> {
+    let mut fingerprint = commit_diff_fingerprint(repo, commit, base, config)?;
+    let gitignored = gitignored_paths(repo, commit, fingerprint.keys()).block_on()?;
+    for path in gitignored {
+        fingerprint.remove(&path);
+    }
+    if config.ignore_empty_files {
+        remove_empty_files(repo, &mut fingerprint)?;
+    }
+    Ok(fingerprint)
+}
+
+/// Removes the empty files `fingerprint` adds or deletes, for
+/// `ignore_empty_files`.
+fn remove_empty_files(
+    repo: &ReadonlyRepo,
+    fingerprint: &mut BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+) -> Result<()> {
+    let mut empty = Vec::new();
+    for (path, diff) in fingerprint.iter() {
+        if adds_or_deletes_empty_file(repo, path, diff).block_on()? {
+            empty.push(path.clone());
+        }
+    }
+    for path in empty {
+        fingerprint.remove(&path);
+    }
+    Ok(())
+}
+
+/// Which of `paths` the `.gitignore` files in `commit`'s tree ignore, as
+/// jj's snapshot would. An ignored path can still be in a diff if it was
+/// tracked before being ignored, like a committed build artifact.
+async fn gitignored_paths<'a>(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    paths: impl Iterator<Item = &'a RepoPathBuf>,
+) -> Result<Vec<RepoPathBuf>> {
+    let tree = commit.tree();
+    // The rules in effect in each directory, by prefix (`""`, `"src/"`).
+    let mut rules: HashMap<String, Arc<GitIgnoreFile>> = HashMap::new();
+    let mut ignored = Vec::new();
+    for path in paths {
+        let components: Vec<_> = path.as_internal_file_string().split('/').collect();
+        let mut prefix = String::new();
+        let mut parent = GitIgnoreFile::empty();
+        for (index, component) in components.iter().enumerate() {
+            let dir_rules = match rules.get(&prefix) {
+                Some(dir_rules) => dir_rules.clone(),
+                None => {
+                    let dir_rules = chain_gitignore(repo, &tree, &parent, &prefix).await?;
+                    rules.insert(prefix.clone(), dir_rules.clone());
+                    dir_rules
+                }
+            };
+            // Directories match with a trailing `/`, so `build/` rules
+            // cover everything under it.
+            let is_file = index + 1 == components.len();
+            let candidate = if is_file {
+                format!("{prefix}{component}")
+            } else {
+                format!("{prefix}{component}/")
+            };
+            if dir_rules.matches(&candidate) {
+                ignored.push(path.clone());
+                break;
+            }
+            prefix = format!("{prefix}{component}/");
+            parent = dir_rules;
+        }
+    }
+    Ok(ignored)
+}
+
+/// `parent` chained with the `.gitignore` in directory `prefix` of `tree`,
+/// if there is one. A malformed file is skipped with a debug note.
+async fn chain_gitignore(
+    repo: &ReadonlyRepo,
+    tree: &MergedTree,
+    parent: &Arc<GitIgnoreFile>,
+    prefix: &str,
+) -> Result<Arc<GitIgnoreFile>> {
+    let path = RepoPathBuf::from_internal_string(format!("{prefix}.gitignore"))?;
+    let Some(Some(TreeValue::File { id, .. })) = tree.path_value(&path)?.into_resolved().ok()
+    else {
+        return Ok(parent.clone());
+    };
+    let content = read_file(repo, &path, &id).await?;
+    let file_name = path.as_internal_file_string();
+    Ok(parent
+        .chain(prefix, Path::new(file_name), &content)
+        .unwrap_or_else(|e| {
+            debug!("skipping malformed {file_name}: {e}");
+            parent.clone()
+        }))
+}
+
+/// [`classified_fingerprint`], read from `options.fingerprint_cache` if
+/// there, else computed and written there; cache errors only cost the
+/// recompute. Entries are keyed by the commit, the base and the settings that
+/// shape the fingerprint, so they never go out of date.
+fn cached_fingerprint(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    base: &FingerprintBase,
+    options: &CheckOptions,
+) -> Result<BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>> {
+    let Some(cache_dir) = &options.fingerprint_cache else {
+        return classified_fingerprint(repo, commit, base, &options.config);
+    };
+    let mut key = commit.id().hex();
+    if let FingerprintBase::ForkPoint(base_ids) = base {
+        key.push_str(":fork");
+        for id in base_ids {
+            key.push(':');
+            key.push_str(&id.hex());
+        }
+    }
+    key.push('\n');
+    key.push_str(&options.config.fingerprint_inputs());
+    let file = cache_dir.join(&encode_hex(&blake2b_hash(&key))[..32]);
+    let cached = fs::read_to_string(&file)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .and_then(decode_fingerprint);
+    if let Some(fingerprint) = cached {
+        return Ok(fingerprint);
+    }
+
+    let fingerprint = classified_fingerprint(repo, commit, base, &options.config)?;
+    let written = fs::create_dir_all(cache_dir).and_then(|()| {
+        fs::write(
+            &file,
+            serde_json::json!(encode_fingerprint(&fingerprint)).to_string(),
+        )
+    });
+    if let Err(e) = written {
+        debug!("failed to cache fingerprint at {}: {e}", file.display());
+    }
+    Ok(fingerprint)
+}
+
+/// A fingerprint in the cache: each path's [`Diff`] as the terms of its
+/// `before` and `after` merges.
+type CachedFingerprint = BTreeMap<String, [Vec<Option<CachedTreeValue>>; 2]>;
+
+/// A [`TreeValue`] in the fingerprint cache, with hex ids.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CachedTreeValue {
+    #[serde(rename_all = "camelCase")]
+    File {
+        id: String,
+        executable: bool,
+        copy_id: String,
+    },
+    Symlink(String),
+    Tree(String),
+    GitSubmodule(String),
+}
+
+impl From<&TreeValue> for CachedTreeValue {
+    fn from(value: &TreeValue) -> Self {
+        match value {
+            TreeValue::File {
+                id,
+                executable,
+                copy_id,
+            } => Self::File {
+                id: id.hex(),
+                executable: *executable,
+                copy_id: copy_id.hex(),
+            },
+            TreeValue::Symlink(id) => Self::Symlink(id.hex()),
+            TreeValue::Tree(id) => Self::Tree(id.hex()),
+            TreeValue::GitSubmodule(id) => Self::GitSubmodule(id.hex()),
+        }
+    }
+}
+
+impl CachedTreeValue {
+    /// The [`TreeValue`], or `None` if an id isn't hex.
+    fn to_tree_value(&self) -> Option<TreeValue> {
+        Some(match self {
+            Self::File {
+                id,
+                executable,
+                copy_id,
+            } => TreeValue::File {
+                id: FileId::new(decode_hex(id)?),
+                executable: *executable,
+                copy_id: CopyId::new(decode_hex(copy_id)?),
+            },
+            Self::Symlink(id) => TreeValue::Symlink(SymlinkId::new(decode_hex(id)?)),
+            Self::Tree(id) => TreeValue::Tree(TreeId::new(decode_hex(id)?)),
+            Self::GitSubmodule(id) => TreeValue::GitSubmodule(CommitId::new(decode_hex(id)?)),
+        })
+    }
+}
+
+fn encode_fingerprint(
+    fingerprint: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+) -> CachedFingerprint {
+    let terms = |value: &MergedTreeValue| {
+        value
+            .iter()
+            .map(|term| term.as_ref().map(CachedTreeValue::from))
+            .collect()
+    };
+    fingerprint
+        .iter()
+        .map(|(path, diff)| {
+            (
+                path.as_internal_file_string().to_owned(),
+                [terms(&diff.before), terms(&diff.after)],
+            )
+        })
+        .collect()
+}
+
+/// The fingerprint `cached` encodes, or `None` if any entry is malformed.
+fn decode_fingerprint(
+    cached: CachedFingerprint,
+) -> Option<BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>> {
+    let merge = |terms: Vec<Option<CachedTreeValue>>| -> Option<MergedTreeValue> {
+        // A merge has one more added term than removed ones.
+        if terms.len() % 2 == 0 {
+            return None;
+        }
+        let values = terms
+            .iter()
+            .map(|term| match term {
+                Some(value) => value.to_tree_value().map(Some),
+                None => Some(None),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Merge::from_vec(values))
+    };
+    cached
+        .into_iter()
+        .map(|(path, [before, after])| {
+            let path = RepoPathBuf::from_internal_string(path).ok()?;
+            Some((
+                path,
+                Diff {
+                    before: merge(before)?,
+                    after: merge(after)?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Deletes `fingerprint_cache` entries in `cache_dir` older than
+/// [`FINGERPRINT_CACHE_MAX_AGE`] at `now`, so the cache doesn't grow without
+/// bound. Fails open: entries that can't be inspected or removed stay.
+fn prune_fingerprint_cache(cache_dir: &Path, now: SystemTime) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| {
+                now.duration_since(modified)
+                    .is_ok_and(|age| age > FINGERPRINT_CACHE_MAX_AGE)
+            });
+        if expired && let Err(e) = fs::remove_file(entry.path()) {
+            debug!("failed to prune {}: {e}", entry.path().display());
+        }
+    }
+}
+
+/// Whether `diff` adds or deletes a file with no content, like a placeholder
+/// `__init__.py` or `.gitkeep`.
+async fn adds_or_deletes_empty_file(
+    repo: &ReadonlyRepo,
+    path: &RepoPath,
+    diff: &Diff<MergedTreeValue>,
+) -> Result<bool> {
+    let value = match (diff.before.is_absent(), diff.after.is_absent()) {
+        (true, false) => &diff.after,
+        (false, true) => &diff.before,
+        _ => return Ok(false),
+    };
+    let Some(Some(TreeValue::File { id, .. })) = value.as_resolved() else {
+        return Ok(false);
+    };
+    let mut reader = repo.store().read_file(path, id).await?;
+    let mut byte = [0; 1];
+    Ok(reader.read(&mut byte).await? == 0)
+}
+
+/// Whether `diff`, a change's own diff, touches a file `config` classifies
+/// as [`FileClass::Sensitive`].
+fn touches_sensitive_file(
+    diff: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    config: &Config,
+) -> bool {
+    diff.keys()
+        .any(|path| config.classify(path) == FileClass::Sensitive)
+}
+
+/// Computes a fingerprint of a commit's diff from its parent(s), or from its
+/// fork point with the configured base (see [`FingerprintBase`]).
+///
+/// Returns a sorted map of `(path → (before, after))` tree value pairs. Two
+/// commits have the same logical content iff their fingerprints are equal,
+/// regardless of what parents they sit on.
+///
+/// A merge commit's parent tree is the auto-merge of its parents, so its
+/// fingerprint is only what it changes on top of that merge (typically
+/// conflict resolutions), and rebasing either side doesn't move it unless
+/// the resolution has to change too.
+///
+/// Files `config` classifies as [`FileClass::Ignore`] aren't diffed at all.
+fn commit_diff_fingerprint(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    base: &FingerprintBase,
+    config: &Config,
+) -> Result<BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>> {
+    let tree = commit.tree();
+    let parent_tree = match base {
+        FingerprintBase::Parent => commit.parent_tree(repo)?,
+        FingerprintBase::ForkPoint(base_ids) => fork_point_tree(repo, commit, base_ids)?,
+    };
+
+    let mut fingerprint = BTreeMap::new();
+    #[cfg(test)]
+    FINGERPRINT_DIFFS.with(|diffs| diffs.set(diffs.get() + 1));
+    let mut stream = parent_tree.diff_stream(&tree, &UnignoredMatcher(config));
+
+    async {
+        use futures::StreamExt as _;
+        while let Some(entry) = stream.next().await {
+            let diff = entry.values?;
+            fingerprint.insert(entry.path, diff);
+        }
+        anyhow::Ok(())
+    }
+    .block_on()?;
+
+    Ok(fingerprint)
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Calls to [`commit_diff_fingerprint`] on this thread, so tests can tell
+    /// cache hits from recomputes.
+    static FINGERPRINT_DIFFS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Matches the files `config` doesn't classify as [`FileClass::Ignore`].
+/// Globs can match below any directory, so every directory is visited.
+#[derive(Debug)]
+struct UnignoredMatcher<'a>(&'a Config);
+
+impl Matcher for UnignoredMatcher<'_> {
+    fn matches(&self, file: &RepoPath) -> bool {
+        self.0.classify(file) != FileClass::Ignore
+    }
+
+    fn visit(&self, _dir: &RepoPath) -> Visit {
+        Visit::Specific {
+            dirs: VisitDirs::All,
+            files: VisitFiles::All,
+        }
+    }
+}
+
+/// Returns the tree of `commit`'s fork point with `base_ids`: the newest
+/// common ancestor. Falls back to the root commit for unrelated history.
+///
+/// Each commit gets its own fork point, so a describe-time predecessor that
+/// sat on an older trunk is compared against that older fork point, and
+/// rebasing the stack onto a newer trunk doesn't register as drift.
+fn fork_point_tree(
+    repo: &ReadonlyRepo,
+    commit: &Commit,
+    base_ids: &[CommitId],
+) -> Result<MergedTree> {
+    let fork_points = ResolvedRevsetExpression::commits(vec![commit.id().clone()])
+        .ancestors()
+        .intersection(&ResolvedRevsetExpression::commits(base_ids.to_vec()).ancestors())
+        .heads();
+    // Criss-cross history can have several fork points; evaluation yields the
+    // newest first, which is as good a pick as any.
+    let fork_point_id = fork_points.evaluate(repo)?.iter().next().transpose()?;
+    let fork_point = match fork_point_id {
+        Some(id) => repo.store().get_commit(&id)?,
+        None => repo.store().root_commit(),
+    };
+    Ok(fork_point.tree())
+}
+
+/// Returns the set of paths whose diff-from-parent entry differs between two
+/// fingerprints. This is the set of files that "changed" between two points
+/// in a commit's evolution, sorted and without duplicates.
+fn diff_fingerprint_changes(
+    described: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    current: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+) -> Vec<RepoPathBuf> {
+    let mut changed = BTreeSet::new();
+
+    // Paths present in current but absent or different in described.
+    for (path, cur_diff) in current {
+        match described.get(path) {
+            Some(desc_diff) if desc_diff == cur_diff => {}
+            _ => {
+                changed.insert(path.clone());
+            }
+        }
+    }
+
+    // Paths removed from the diff (present in described, absent in current).
+    for path in described.keys() {
+        if !current.contains_key(path) {
+            changed.insert(path.clone());
+        }
+    }
+
+    changed.into_iter().collect()
+}
+
+/// [`diff_fingerprint_changes`], less the paths that differ only in
+/// whitespace when `whitespace_insensitive` is set.
+fn drifted_paths(
+    repo: &ReadonlyRepo,
+    described: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    current: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    config: &Config,
+) -> Result<Vec<RepoPathBuf>> {
+    let changed = diff_fingerprint_changes(described, current);
+    if !config.whitespace_insensitive {
+        return Ok(changed);
+    }
+    async {
+        let mut drifted = Vec::new();
+        for path in changed {
+            // A path in only one fingerprint has drifted unless its diff there
+            // is itself whitespace-only.
+            let same = match (described.get(&path), current.get(&path)) {
+                (Some(before), Some(after)) => {
+                    same_text(repo, &path, &before.before, &after.before).await?
+                        && same_text(repo, &path, &before.after, &after.after).await?
+                }
+                (Some(diff), None) | (None, Some(diff)) => {
+                    same_text(repo, &path, &diff.before, &diff.after).await?
+                }
+                (None, None) => true,
+            };
+            if !same {
+                drifted.push(path);
+            }
+        }
+        anyhow::Ok(drifted)
+    }
+    .block_on()
+}
+
+/// Whether two tree values at `path` are equal, or are text files with the
+/// same mode whose contents differ only in trailing whitespace and line
+/// endings.
+async fn same_text(
+    repo: &ReadonlyRepo,
+    path: &RepoPath,
+    a: &MergedTreeValue,
+    b: &MergedTreeValue,
+) -> Result<bool> {
+    if a == b {
+        return Ok(true);
+    }
+    let (
+        Some(Some(TreeValue::File {
+            id: a_id,
+            executable: a_exec,
+            ..
+        })),
+        Some(Some(TreeValue::File {
+            id: b_id,
+            executable: b_exec,
+            ..
+        })),
+    ) = (a.as_resolved(), b.as_resolved())
+    else {
+        return Ok(false);
+    };
+    if a_exec != b_exec {
+        return Ok(false);
+    }
+    let (Some(a_text), Some(b_text)) = (
+        normalized_text(repo, path, a_id).await?,
+        normalized_text(repo, path, b_id).await?,
+    ) else {
+        return Ok(false);
+    };
+    Ok(a_text == b_text)
+}
+
+/// The file's contents with trailing whitespace and line endings
+/// normalized, or `None` for binary (NUL bytes or not UTF-8) files.
+async fn normalized_text(
+    repo: &ReadonlyRepo,
+    path: &RepoPath,
+    id: &FileId,
+) -> Result<Option<String>> {
+    let content = read_file(repo, path, id).await?;
+    if content.contains(&0) {
+        return Ok(None);
+    }
+    let Ok(text) = String::from_utf8(content) else {
+        return Ok(None);
+    };
+    Ok(Some(
+        text.lines()
+            .map(str::trim_end)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    ))
+}
+
+/// The full contents of file `id` at `path`.
+async fn read_file(repo: &ReadonlyRepo, path: &RepoPath, id: &FileId) -> Result<Vec<u8>> {
+    let mut reader = repo.store().read_file(path, id).await?;
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).await?;
+    Ok(content)
+}
+
+/// [`LineCount`]s for `paths`' diffs in `fingerprint`. Paths whose diff
+/// involves a conflict, or something other than a file, are left out.
+fn line_counts(
+    repo: &ReadonlyRepo,
+    fingerprint: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    paths: &[RepoPathBuf],
+) -> Result<BTreeMap<RepoPathBuf, LineCount>> {
+    async {
+        let mut counts = BTreeMap::new();
+        for path in paths {
+            let Some(diff) = fingerprint.get(path) else {
+                continue;
+            };
+            let (Some(before), Some(after)) = (
+                file_content(repo, path, &diff.before).await?,
+                file_content(repo, path, &diff.after).await?,
+            ) else {
+                continue;
+            };
+            counts.insert(path.clone(), count_lines(&before, &after));
+        }
+        anyhow::Ok(counts)
+    }
+    .block_on()
+}
+
+/// The contents of the file `value` holds, empty if absent, or `None` for
+/// a conflict or a non-file.
+async fn file_content(
+    repo: &ReadonlyRepo,
+    path: &RepoPath,
+    value: &MergedTreeValue,
+) -> Result<Option<Vec<u8>>> {
+    match value.as_resolved() {
+        Some(None) => Ok(Some(Vec::new())),
+        Some(Some(TreeValue::File { id, .. })) => Ok(Some(read_file(repo, path, id).await?)),
+        _ => Ok(None),
+    }
+}
+
+/// Total lines changed in `paths` between the described and current
+/// fingerprints, comparing each path's contents after either diff. A path
+/// missing from one fingerprint is unchanged from its base there. `None` if
+/// any path can't be line-diffed.
+fn lines_changed_since(
+    repo: &ReadonlyRepo,
+    described: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    current: &BTreeMap<RepoPathBuf, Diff<MergedTreeValue>>,
+    paths: &[RepoPathBuf],
+) -> Result<Option<usize>> {
+    async {
+        let mut total = 0;
+        for path in paths {
+            let (then, now) = match (described.get(path), current.get(path)) {
+                (Some(then), Some(now)) => (&then.after, &now.after),
+                (Some(then), None) => (&then.after, &then.before),
+                (None, Some(now)) => (&now.before, &now.after),
+                (None, None) => continue,
+            };
+            let (Some(then), Some(now)) = (
+                file_content(repo, path, then).await?,
+                file_content(repo, path, now).await?,
+            ) else {
+                return Ok(None);
+            };
+            match count_lines(&then, &now) {
+                LineCount::Text {
+                    additions,
+                    deletions,
+                } => total += additions + deletions,
+                LineCount::Binary => return Ok(None),
+            }
+        }
+        anyhow::Ok(Some(total))
+    }
+    .block_on()
+}
+
+/// Lines added and removed going from `before` to `after`, per jj's line
+/// diff. `Binary` if either isn't UTF-8.
+fn count_lines(before: &[u8], after: &[u8]) -> LineCount {
+    if std::str::from_utf8(before).is_err() || std::str::from_utf8(after).is_err() {
+        return LineCount::Binary;
+    }
+    let lines = |content: &[u8]| content.split_inclusive(|&byte| byte == b'\n').count();
+    let (mut additions, mut deletions) = (0, 0);
+    for hunk in ContentDiff::by_line([before, after]).hunks() {
+        if matches!(hunk.kind, DiffHunkKind::Different) {
+            deletions += lines(hunk.contents[0]);
+            additions += lines(hunk.contents[1]);
+        }
+    }
+    LineCount::Text {
+        additions,
+        deletions,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Output
+// ---------------------------------------------------------------------------
+
+/// How a run reports stale changes, from `--stop` and `--check`. Ordered
+/// so the later variant wins when both flags are given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum Mode {
+    /// The PostToolUse hook: newly stale changes as context for Claude.
+    #[default]
+    Advisory,
+    /// The Stop hook (`--stop`): block session exit, within a retry budget.
+    Stop,
+    /// `--check`: the report on stderr, exiting [`CHECK_EXIT_CODE`].
+    Check,
+}
+
+/// Output appropriate for the mode. `counts` are for the summary line.
+///
+/// - **Stop mode**: the [`StopDecision`] from [`decide_stop`]. Blocking
+///   prints on stderr and exits with its code, or with `stop_json` prints a
+///   decision object on stdout. The retry cap (see
+///   [`StopDecision::Allow`]) prevents infinite loops when Claude can't/won't
+///   fix the descriptions; the counter resets per prompt via a
+///   `UserPromptSubmit` hook, so each user prompt gets a fresh budget of
+///   `max_stop_retries` attempts.
+/// - **Advisory**: JSON on stdout for Claude Code hook protocol.
+/// - **Check**: the report on stderr, exiting [`CHECK_EXIT_CODE`].
+fn hook_output(
+    stale: &[StalenessInfo],
+    counts: StaleCounts,
+    mode: Mode,
+    config: &Config,
+    retry_file: &Path,
+    session_id: &str,
+) -> Result<Output> {
+    if mode == Mode::Check {
+        return Ok(Output::Block {
+            message: format_staleness_message(
+                stale,
+                counts,
+                &config.messages,
+                Some(config.remediation_command()),
+            ),
+            exit_code: CHECK_EXIT_CODE,
+        });
+    }
+    if mode == Mode::Advisory {
+        let msg = cap_message(
+            format_staleness_message(
+                stale,
+                counts,
+                &config.messages,
+                Some(config.remediation_command()),
+            ),
+            stale.len(),
+            config.max_message_bytes(),
+        );
+        let structured = env::var_os("ACTIVE_DESCRIPTIONS_JSON")
+            .is_some_and(|value| !value.is_empty() && value != "0");
+        return Ok(advisory_output(&msg, stale, structured));
+    }
+    let decision = decide_stop(stale, counts, config, retry_file, session_id)?;
+    Ok(match decision {
+        StopDecision::Block { message, .. } if config.stop_json => {
+            let output = serde_json::json!({ "decision": "block", "reason": message });
+            Output::Stdout(format!("{output}\n"))
+        }
+        StopDecision::Block { message, exit_code } => Output::Block { message, exit_code },
+        StopDecision::Notice { message } => notice_output(&message),
+        StopDecision::Allow => Output::Nothing,
+    })
+}
+
+/// How stop mode responds to a stale set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StopDecision {
+    /// Block session exit: `message` on stderr, then exit with `exit_code`.
+    Block { message: String, exit_code: i32 },
+    /// Only [`Severity::Warn`] reasons: tell the user without blocking.
+    Notice { message: String },
+    /// The retry budget is spent; let the session stop.
+    Allow,
+}
+
+/// Decides how stop mode responds to `stale`, with `counts` for the
+/// summary line. Blocking consumes one of `session_id`'s retries (see
+/// [`claim_stop_retry`]).
+fn decide_stop(
+    stale: &[StalenessInfo],
+    counts: StaleCounts,
+    config: &Config,
+    retry_file: &Path,
+    session_id: &str,
+) -> Result<StopDecision> {
+    let max_bytes = config.max_message_bytes();
+    // The commands are listed after the message, with the files they cover.
+    let msg = format_staleness_message(stale, counts, &config.messages, None);
+    if !should_block(stale, config) {
+        return Ok(StopDecision::Notice {
+            message: cap_message(msg, stale.len(), max_bytes),
+        });
+    }
+    if !claim_stop_retry(retry_file, session_id, config.max_stop_retries())? {
+        return Ok(StopDecision::Allow);
+    }
+    let commands = remediation_commands(stale, config.remediation_command());
+    let instructions = "You MUST update all stale descriptions before stopping. \
+                        Ensure the active-descriptions:describe skill is loaded, \
+                        then follow it for each stale change.";
+    let body = cap_message(
+        format!("{msg}\n\nTo update them:\n{commands}"),
+        stale.len(),
+        max_bytes.saturating_sub(instructions.len() + 1),
+    );
+    Ok(StopDecision::Block {
+        message: format!("{body}\n{instructions}"),
+        exit_code: stop_exit_code(config),
+    })
+}
+
+/// Truncates `msg` to about `max_bytes` at a line boundary, behind a header
+/// counting all `stale_count` changes and followed by a marker. The header
+/// is kept even if it alone exceeds `max_bytes`.
+fn cap_message(msg: String, stale_count: usize, max_bytes: usize) -> String {
+    if msg.len() <= max_bytes {
+        return msg;
+    }
+    let header = format!("{stale_count} stale changes; showing the first few.\n");
+    let marker = "\n(output truncated)";
+    let budget = max_bytes.saturating_sub(header.len() + marker.len());
+    let mut cut = budget.min(msg.len());
+    while !msg.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let cut = msg[..cut].rfind('\n').unwrap_or(0);
+    format!("{header}{}{marker}", &msg[..cut])
+}
+
+/// One indented `template` line per stale change, with `{change_id}`
+/// substituted and the changed files as a trailing comment.
+fn remediation_commands(stale: &[StalenessInfo], template: &str) -> String {
+    let mut out = String::new();
+    for info in stale {
+        out.push_str("  ");
+        out.push_str(&template.replace("{change_id}", &info.change_id_short));
+        if !info.changed_files.is_empty() {
+            let files: Vec<_> = info
+                .changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string())
+                .collect();
+            out.push_str("  # ");
+            out.push_str(&files.join(", "));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The exit code that blocks in stop mode, for orchestrators other than
+/// Claude Code: `ACTIVE_DESCRIPTIONS_STOP_EXIT_CODE` if set, else
+/// `stop_exit_code` from config, else [`DEFAULT_STOP_EXIT_CODE`].
+fn stop_exit_code(config: &Config) -> i32 {
+    let from_env = env::var("ACTIVE_DESCRIPTIONS_STOP_EXIT_CODE").ok();
+    resolve_stop_exit_code(from_env.as_deref(), config.stop_exit_code)
+}
+
+/// Picks the env override over the configured code. Codes outside 1–125
+/// (0 wouldn't block; higher ones are reserved by shells) fall back to the
+/// default.
+fn resolve_stop_exit_code(from_env: Option<&str>, configured: Option<i64>) -> i32 {
+    let code = match from_env {
+        Some(value) => value.trim().parse().ok(),
+        None => configured,
+    };
+    match code {
+        None => DEFAULT_STOP_EXIT_CODE,
+        Some(code) => match i32::try_from(code) {
+            Ok(code) if (1..=125).contains(&code) => code,
+            _ => {
+                debug!("stop exit code {code} is outside 1-125; using {DEFAULT_STOP_EXIT_CODE}");
+                DEFAULT_STOP_EXIT_CODE
+            }
+        },
+    }
+}
+
+/// Builds a human-readable staleness summary including changed file paths.
+/// With more than one stale change, a leading line gives their number out
+/// of the candidates checked, per `counts`. With a `remediation` template (see
+/// [`Config::remediation_command`]), each change ends with the command to
+/// update it. Change IDs are shortened to [`CheckOptions::change_id_len`],
+/// grown where needed to tell the candidates apart.
+fn format_staleness_message(
+    stale: &[StalenessInfo],
+    counts: StaleCounts,
+    messages: &Messages,
+    remediation: Option<&str>,
+) -> String {
+    let changes = stale
+        .iter()
+        .map(|info| {
+            let mut msg = format_stale_change(info, messages);
+            if let Some(template) = remediation {
+                msg.push_str("\n  To update: ");
+                msg.push_str(&template.replace("{change_id}", &info.change_id_short));
+            }
+            msg
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let total = counts.stale.max(stale.len());
+    if total < 2 {
+        return changes;
+    }
+    format!(
+        "{total} of {} changes have stale descriptions:\n{changes}",
+        counts.checked.max(total)
+    )
+}
+
+/// How many candidates are stale out of how many were checked, for the
+/// summary line of [`format_staleness_message`]. `stale` can exceed the
+/// changes listed: advisory runs only list the newly stale ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StaleCounts {
+    stale: usize,
+    checked: usize,
+}
+
+impl StaleCounts {
+    /// Every one of `stale` is listed, out of `checked` candidates.
+    fn of(stale: &[StalenessInfo], checked: usize) -> Self {
+        Self {
+            stale: stale.len(),
+            checked,
+        }
+    }
+}
+
+/// Describes one stale change, followed by its changed files (if any) on an
+/// indented line. The headline is `messages`' template for the reason, if
+/// configured.
+fn format_stale_change(info: &StalenessInfo, messages: &Messages) -> String {
+    let files: Vec<_> = info
+        .changed_files
+        .iter()
+        .map(|f| f.as_internal_file_string().to_owned())
+        .collect();
+    let mut msg = match messages.template(info.reason) {
+        Some(template) => template
+            .replace("{change}", &info.change_id_short)
+            .replace("{files}", &files.join(", "))
+            .replace("{count}", &files.len().to_string()),
+        None => builtin_headline(info),
+    };
+    if !files.is_empty() {
+        let annotated: Vec<_> = info
+            .changed_files
+            .iter()
+            .zip(&files)
+            .map(|(path, file)| match info.line_counts.get(path) {
+                Some(count) => format!("{file} ({count})"),
+                None => file.clone(),
+            })
+            .collect();
+        msg.push_str("\n  Changed: ");
+        msg.push_str(&annotated.join(", "));
+    }
+    if let Some(description) = &info.description
+        && !description.is_empty()
+    {
+        msg.push_str("\n  Current description: ");
+        msg.push_str(&description.replace('\n', "\n    "));
+    }
+    msg
+}
+
+/// The built-in first line of [`format_stale_change`].
+fn builtin_headline(info: &StalenessInfo) -> String {
+    match info.reason {
+        StaleReason::EmptyDescription => format!(
+            "Missing description: change {} has no description.",
+            info.change_id_short
+        ),
+        StaleReason::ContentChanged => format!(
+            "Stale description: change {} modified since last described.",
+            info.change_id_short
+        ),
+        StaleReason::MissingBody => format!(
+            "Description needs a body: change {} touches {} files but has only a subject line.",
+            info.change_id_short,
+            info.changed_files.len()
+        ),
+        StaleReason::BannedPhrase => format!(
+            "Banned phrase in description: change {} contains \"{}\".",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::RestatesFilename => format!(
+            "Description only restates filenames: change {} should say what changed and why.",
+            info.change_id_short
+        ),
+        StaleReason::SubjectTooThin => format!(
+            "Subject too thin: change {} says too little after its type prefix.",
+            info.change_id_short
+        ),
+        StaleReason::PatternMismatch => format!(
+            "Subject doesn't match the required pattern: change {} should match \"{}\".",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::DescriptionMismatchesDiff => format!(
+            "Description describes another change: change {} names files that change {} \
+             touches, not its own.",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::ReferencesMissingFile => format!(
+            "Description names a file the change no longer touches: change {} mentions {}.",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::PlaceholderDescription => format!(
+            "Placeholder description: change {} is still described as \"{}\".",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::DescriptionTooShort => format!(
+            "Description too short: change {} needs at least {} characters.",
+            info.change_id_short,
+            info.detail.as_deref().unwrap_or_default()
+        ),
+        StaleReason::NonConventionalFormat => format!(
+            "Subject isn't a Conventional Commit: change {} should start with \
+             \"type(scope): \".",
+            info.change_id_short
+        ),
+    }
+}
+
+/// Whether any stale change has a blocking-severity reason.
+fn should_block(stale: &[StalenessInfo], config: &Config) -> bool {
+    stale
+        .iter()
+        .any(|info| severity(info, config) == Severity::Block)
+}
+
+/// How seriously to take `info`: its reason's [`Severity`], except that
+/// drift confined to `trivial_paths` only warns.
+fn severity(info: &StalenessInfo, config: &Config) -> Severity {
+    let trivial = info.reason.is_drift()
+        && !info.changed_files.is_empty()
+        && info
+            .changed_files
+            .iter()
+            .all(|path| config.is_trivial(path));
+    if trivial {
+        Severity::Warn
+    } else {
+        config.severity.of(info.reason)
+    }
+}
+
+/// Stop mode with only warn-level reasons: a message for the user that
+/// doesn't block session exit.
+fn notice_output(msg: &str) -> Output {
+    let output = serde_json::json!({ "systemMessage": msg });
+    Output::Stdout(format!("{output}\n"))
+}
+
+/// Advisory mode: JSON on stdout for Claude Code PostToolUse hook. With
+/// `structured`, a `staleChanges` array sits next to the message for tools
+/// that would rather not parse it.
+fn advisory_output(msg: &str, stale: &[StalenessInfo], structured: bool) -> Output {
+    let mut output = serde_json::json!({
+        "hookSpecificOutput": {
+            "additionalContext": msg
+        }
+    });
+    if structured {
+        let changes: Vec<_> = stale.iter().map(report::StaleChange::from).collect();
+        output["hookSpecificOutput"]["staleChanges"] = serde_json::json!(changes);
+    }
+    Output::Stdout(format!("{output}\n"))
+}
+
+/// The Claude session id, or `"unknown"` outside a session.
+fn session_id() -> String {
+    env::var("CLAUDE_SESSION_ID").unwrap_or_else(|_| "unknown".into())
+}
+
+/// Removes the retry file so the stop hook can re-arm. Called when
+/// descriptions are found to be up-to-date.
+fn reset_stop_retries(retry_file: &Path) {
+    let _ = fs::remove_file(retry_file);
+}
+
+/// Merges stale entries for the same change, e.g. divergent commits or a
+/// commit listed twice, into the first one, with the changed files of all
+/// of them, sorted.
+fn merge_duplicate_changes(stale: Vec<StalenessInfo>) -> Vec<StalenessInfo> {
+    let mut merged: Vec<StalenessInfo> = Vec::with_capacity(stale.len());
+    for info in stale {
+        match merged
+            .iter_mut()
+            .find(|kept| kept.change_id_short == info.change_id_short)
+        {
+            Some(kept) => {
+                kept.changed_files.extend(info.changed_files);
+                kept.changed_files.sort();
+                kept.changed_files.dedup();
+                for (path, count) in info.line_counts {
+                    kept.line_counts.entry(path).or_insert(count);
+                }
+            }
+            None => merged.push(info),
+        }
+    }
+    merged
+}
+
+/// Drops changes that were already stale on the previous run, recorded in
+/// `state_file`, then records the current stale set for the next run.
+///
+/// Changes are recorded by full change ID, looked up in `full_ids` (see
+/// [`full_change_ids`]), so changing `change_id_length` doesn't resurface
+/// them. Only the latest set is kept, so a change that goes clean and later
+/// drifts again counts as newly stale. The file is session-scoped, so a new
+/// session starts from an empty baseline. State errors fail open to the
+/// full set.
+fn retain_newly_stale(
+    state_file: &Path,
+    stale: Vec<StalenessInfo>,
+    full_ids: &HashMap<String, String>,
+) -> Vec<StalenessInfo> {
+    let previous = fs::read_to_string(state_file).unwrap_or_default();
+    let previous: HashSet<&str> = previous.lines().collect();
+    let full_id = |info: &StalenessInfo| -> String {
+        full_ids
+            .get(&info.change_id_short)
+            .unwrap_or(&info.change_id_short)
+            .clone()
+    };
+
+    let current: Vec<String> = stale.iter().map(full_id).collect();
+    if let Err(e) = fs::write(state_file, current.join("\n")) {
+        debug!(
+            "failed to write {}: {e}; reporting all stale changes",
+            state_file.display()
+        );
+        return stale;
+    }
+
+    stale
+        .into_iter()
+        .filter(|info| !previous.contains(full_id(info).as_str()))
+        .collect()
+}
+
+/// Consumes one of `session_id`'s `max_retries` blocking attempts,
+/// recorded in `retry_file` as the session id and count on separate lines.
+/// Returns `false` once the budget is spent.
+///
+/// A count stored under a different session id is from a stale file (e.g.
+/// sessions sharing the `"unknown"` fallback id) and restarts from zero.
+fn claim_stop_retry(retry_file: &Path, session_id: &str, max_retries: u32) -> Result<bool> {
+    let stored = fs::read_to_string(retry_file).unwrap_or_default();
+    let retries: u32 = match stored.split_once('\n') {
+        Some((stored_session, count)) if stored_session == session_id => {
+            count.trim().parse().unwrap_or(0)
+        }
+        _ => 0,
+    };
+
+    if retries >= max_retries {
+        return Ok(false);
+    }
+
+    fs::write(retry_file, format!("{session_id}\n{}", retries + 1))
+        .with_context(|| format!("failed to write retry file: {}", retry_file.display()))?;
+    Ok(true)
+}
+
+// ---------------------------------------------------------------------------
+// Doctor: environment diagnostics
+// ---------------------------------------------------------------------------
+
+/// Outcome of a single `--doctor` check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckStatus {
+    Pass,
+    /// Non-critical: the hook still works, but something looks off.
+    Warn,
+    /// Critical: the hook will silently do nothing (fail open).
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DoctorCheck {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+}
+
+impl DoctorCheck {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Runs every environment check and returns a pass/fail report for stdout,
+/// exiting 1 if any critical check failed.
+///
+/// Unlike the hook paths this is explicitly diagnostic, so failures are
+/// reported rather than swallowed.
+fn run_doctor(context: &Context<'_>) -> Output {
+    let runner = context.runner;
+    let mut checks = vec![check_jj_binary(runner)];
+    let mut state_dir = context.state_dir.clone();
+
+    match RunContext::discover(runner) {
+        Ok(ctx) => {
+            if let Some(dir) = context
+                .workspace_state_file("retries", &ctx.workspace_root)
+                .parent()
+            {
+                dir.clone_into(&mut state_dir);
+            }
+            checks.push(DoctorCheck::new(
+                "jj repository",
+                CheckStatus::Pass,
+                ctx.workspace_root.display().to_string(),
+            ));
+            match load_user_settings(&ctx.repo_path) {
+                Ok(settings) => {
+                    let config =
+                        load_config(&ctx.workspace_root, settings.config(), &toml::Table::new());
+                    checks.push(check_repo_loads(&ctx.repo_path, &settings));
+                    checks.push(check_revset(runner, config.revset()));
+                }
+                Err(e) => checks.push(DoctorCheck::new(
+                    "jj config",
+                    CheckStatus::Fail,
+                    format!("{e:#}"),
+                )),
+            }
+        }
+        Err(e) => checks.push(DoctorCheck::new(
+            "jj repository",
+            CheckStatus::Fail,
+            format!("{e:#}"),
+        )),
+    }
+
+    checks.push(check_state_dir(&state_dir));
+
+    let text = format_doctor_report(&checks);
+    if checks.iter().any(|c| c.status == CheckStatus::Fail) {
+        Output::Report { text, exit_code: 1 }
+    } else {
+        Output::Stdout(text)
+    }
+}
+
+/// Checks that `jj` is on `PATH` and matches the linked jj-lib version.
+fn check_jj_binary(runner: &dyn CommandRunner) -> DoctorCheck {
+    const NAME: &str = "jj binary";
+
+    let version = match runner.run_jj(&["--version"]) {
+        Ok(out) => out.trim().to_owned(),
+        Err(e) => return DoctorCheck::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    };
+
+    // `jj --version` prints e.g. "jj 0.38.0" or "jj 0.38.0-<hash>".
+    let number = version.strip_prefix("jj ").unwrap_or(&version);
+    let compatible = number
+        .strip_prefix(JJ_LIB_VERSION)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'));
+
+    if compatible {
+        DoctorCheck::new(NAME, CheckStatus::Pass, version)
+    } else {
+        DoctorCheck::new(
+            NAME,
+            CheckStatus::Warn,
+            format!("{version} (plugin is built against jj-lib {JJ_LIB_VERSION})"),
+        )
+    }
+}
+
+/// Checks that jj-lib can load the repo at `repo_path`.
+fn check_repo_loads(repo_path: &Path, settings: &UserSettings) -> DoctorCheck {
+    const NAME: &str = "jj-lib repo load";
+
+    match load_repo(repo_path, settings) {
+        Ok(repo) => DoctorCheck::new(
+            NAME,
+            CheckStatus::Pass,
+            format!("operation {}", repo.op_id().hex()),
+        ),
+        Err(e) => DoctorCheck::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    }
+}
+
+/// Checks that the candidate revset evaluates.
+fn check_revset(runner: &dyn CommandRunner, revset: &str) -> DoctorCheck {
+    const NAME: &str = "candidate revset";
+
+    match try_gather_candidates(runner, revset) {
+        Ok(ids) => DoctorCheck::new(
+            NAME,
+            CheckStatus::Pass,
+            format!("`{revset}` ({} candidates)", ids.len()),
+        ),
+        Err(e) => DoctorCheck::new(NAME, CheckStatus::Fail, format!("{e:#}")),
+    }
+}
+
+/// Checks that the hooks can keep state (retry counts, reported changes,
+/// the fingerprint cache) in `dir`, creating it as they would.
+fn check_state_dir(dir: &Path) -> DoctorCheck {
+    const NAME: &str = "state dir writable";
+
+    let probe = dir.join(format!("claude-stale-desc-doctor-{}", std::process::id()));
+    match fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b"ok")) {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            DoctorCheck::new(NAME, CheckStatus::Pass, dir.display().to_string())
+        }
+        Err(e) => DoctorCheck::new(NAME, CheckStatus::Fail, format!("{}: {e}", dir.display())),
+    }
+}
+
+fn format_doctor_report(checks: &[DoctorCheck]) -> String {
+    use std::fmt::Write as _;
+
+    let mut report = String::new();
+    for check in checks {
+        let label = match check.status {
+            CheckStatus::Pass => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        };
+        let _ = writeln!(report, "[{label:>4}] {}: {}", check.name, check.detail);
+    }
+    report
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_CHANGE_ID_LENGTH;
+    use testutils::{TestRepo, create_tree};
+
+    /// The user-facing change ID, shortened to [`DEFAULT_CHANGE_ID_LENGTH`]
+    /// characters.
+    fn short_change_id(commit: &Commit) -> String {
+        change_id_prefix(commit, DEFAULT_CHANGE_ID_LENGTH)
+    }
+
+    /// Fake `jj` that returns canned stdout keyed by the joined arguments.
+    /// Unknown invocations fail, like a missing binary or a bad revset.
+    #[derive(Default)]
+    struct FakeRunner {
+        responses: std::collections::HashMap<String, String>,
+        calls: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl FakeRunner {
+        fn with(mut self, args: &[&str], stdout: &str) -> Self {
+            self.responses.insert(args.join(" "), stdout.to_owned());
+            self
+        }
+
+        /// Answers `gather_candidates` for `revset`.
+        fn with_candidates(self, revset: &str, stdout: &str) -> Self {
+            self.with(&log_args(&candidate_revset(revset)), stdout)
+        }
+
+        /// How many times `jj <args>` was invoked.
+        fn call_count(&self, args: &[&str]) -> usize {
+            let key = args.join(" ");
+            self.calls.borrow().iter().filter(|c| **c == key).count()
+        }
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run_jj(&self, args: &[&str]) -> Result<String> {
+            self.calls.borrow_mut().push(args.join(" "));
+            match self.responses.get(&args.join(" ")) {
+                Some(stdout) => Ok(stdout.clone()),
+                None => bail!("`jj {}` failed", args.join(" ")),
+            }
+        }
+    }
+
+    /// Arguments `log_commit_ids` passes for `revset`.
+    fn log_args(revset: &str) -> [&str; 6] {
+        [
+            "log",
+            "-r",
+            revset,
+            "--no-graph",
+            "-T",
+            r#"commit_id ++ "\n""#,
+        ]
+    }
+
+    /// Helper: a change described as `described`, edited, then re-described
+    /// as `redescribed`. Returns the repo, the describe commit and the final
+    /// commit.
+    fn redescribed_after_edit(
+        repo: &Arc<ReadonlyRepo>,
+        described: &str,
+        redescribed: &str,
+    ) -> (Arc<ReadonlyRepo>, Commit, Commit) {
+        let mut tx = repo.start_transaction();
+        let created = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let describe = tx
+            .repo_mut()
+            .rewrite_commit(&created)
+            .set_description(described)
+            .write()
+            .expect("describe");
+        let repo = tx.commit("describe").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let edited = tx
+            .repo_mut()
+            .rewrite_commit(&describe)
+            .set_tree(tree(&repo, &[("file.txt", "v2")]))
+            .write()
+            .expect("edit");
+        let repo = tx.commit("edit").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let current = tx
+            .repo_mut()
+            .rewrite_commit(&edited)
+            .set_description(redescribed)
+            .write()
+            .expect("redescribe");
+        let repo = tx.commit("redescribe").expect("tx");
+        (repo, describe, current)
+    }
+
+    /// Helper: create a tree with the given file contents.
+    fn tree(
+        repo: &Arc<ReadonlyRepo>,
+        files: &[(&str, &str)],
+    ) -> jj_lib::merged_tree::MergedTree {
+        let path_contents: Vec<_> = files
+            .iter()
+            .map(|(p, c)| {
+                (
+                    jj_lib::repo_path::RepoPath::from_internal_string(p)
+                        .expect("valid path"),
+                    *c,
+                )
+            })
+            .collect();
+        create_tree(repo, &path_contents)
+    }
+
+    /// Helper: a change described as `description` whose content was then
+    /// edited without updating the description.
+    fn drifted_change(repo: &Arc<ReadonlyRepo>, description: &str) -> (Arc<ReadonlyRepo>, Commit) {
+        let t = tree(repo, &[("file.txt", "v1")]);
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .set_description(description)
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let t2 = tree(&repo, &[("file.txt", "v2")]);
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(t2)
+            .write()
+            .expect("rewrite");
+        tx.repo_mut()
+            .rebase_descendants()
+            .expect("rebase descendants");
+        let repo = tx.commit("edit").expect("tx");
+        (repo, c2)
+    }
+
+    #[test]
+    fn empty_description_is_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let t = tree(repo, &[("file.txt", "content")]);
+        let mut tx = repo.start_transaction();
+        let commit = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        assert!(check_staleness(&repo, commit.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .is_some());
+    }
+
+    #[test]
+    fn described_at_creation_not_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let t = tree(repo, &[("file.txt", "content")]);
+        let mut tx = repo.start_transaction();
+        let commit = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .set_description("feat: add file")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        assert_eq!(
+            check_staleness(&repo, commit.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(CleanReason::DescribedAtCreation)
+        );
+    }
+
+    #[test]
+    fn content_edit_after_describe_is_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Create commit with content + description.
+        let t = tree(repo, &[("file.txt", "v1")]);
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .set_description("feat: initial")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Edit content without updating description.
+        let t2 = tree(&repo, &[("file.txt", "v2")]);
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(t2)
+            .write()
+            .expect("rewrite");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("edit").expect("tx");
+
+        let info = check_staleness(&repo, c2.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(
+            info.changed_files.iter().map(|f| f.as_internal_file_string().to_owned()).collect::<Vec<_>>(),
+            vec!["file.txt"],
+        );
+    }
+
+    #[test]
+    fn describe_after_content_edit_not_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Create commit with content.
+        let t = tree(repo, &[("file.txt", "v1")]);
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .set_description("feat: initial")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Edit content.
+        let t2 = tree(&repo, &[("file.txt", "v2")]);
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(t2)
+            .write()
+            .expect("rewrite");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("edit content").expect("tx");
+
+        // Update description to match.
+        let mut tx = repo.start_transaction();
+        let c3 = tx
+            .repo_mut()
+            .rewrite_commit(&c2)
+            .set_description("feat: updated")
+            .write()
+            .expect("describe");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("describe").expect("tx");
+
+        assert_eq!(
+            check_staleness(&repo, c3.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+    }
+
+    #[test]
+    fn rebase_without_content_change_not_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        // Create parent commit.
+        let parent_tree = tree(repo, &[("base.txt", "base")]);
+        let mut tx = repo.start_transaction();
+        let parent = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], parent_tree)
+            .set_description("base")
+            .write()
+            .expect("write parent");
+        let repo = tx.commit("create parent").expect("tx");
+
+        // Create child commit with description.
+        let child_tree = tree(&repo, &[("base.txt", "base"), ("feat.txt", "feature")]);
+        let mut tx = repo.start_transaction();
+        let child = tx
+            .repo_mut()
+            .new_commit(vec![parent.id().clone()], child_tree)
+            .set_description("feat: add feature")
+            .write()
+            .expect("write child");
+        let repo = tx.commit("create child").expect("tx");
+
+        // Simulate rebase: change parent but keep same diff (feat.txt added).
+        let new_parent_tree =
+            tree(&repo, &[("base.txt", "base"), ("other.txt", "other")]);
+        let mut tx = repo.start_transaction();
+        let new_parent = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], new_parent_tree)
+            .set_description("base v2")
+            .write()
+            .expect("write new parent");
+
+        // Rebased child: new parent, but still adds feat.txt.
+        let rebased_tree = tree(
+            tx.repo().base_repo(),
+            &[
+                ("base.txt", "base"),
+                ("other.txt", "other"),
+                ("feat.txt", "feature"),
+            ],
+        );
+        let rebased = tx
+            .repo_mut()
+            .rewrite_commit(&child)
+            .set_parents(vec![new_parent.id().clone()])
+            .set_tree(rebased_tree)
+            .write()
+            .expect("rebase");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("rebase").expect("tx");
+
+        // Diff is still just "add feat.txt" → not stale.
+        assert_eq!(
+            check_staleness(&repo, rebased.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+    }
+
+    #[test]
+    fn split_preserving_diff_not_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        // Create commit with two files and a description.
+        let t = tree(repo, &[("a.txt", "aaa"), ("b.txt", "bbb")]);
+        let mut tx = repo.start_transaction();
+        let original = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], t)
+            .set_description("feat: add a and b")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Simulate split: first commit gets b.txt, second (rewrite of
+        // original) gets a.txt but is reparented onto first.
+        let first_tree = tree(&repo, &[("b.txt", "bbb")]);
+        let mut tx = repo.start_transaction();
+        let first = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], first_tree)
+            .set_description("feat: add b")
+            .write()
+            .expect("write first");
+
+        // Remaining commit: reparented, full tree includes parent's b.txt.
+        let remaining_tree =
+            tree(tx.repo().base_repo(), &[("a.txt", "aaa"), ("b.txt", "bbb")]);
+        let remaining = tx
+            .repo_mut()
+            .rewrite_commit(&original)
+            .set_parents(vec![first.id().clone()])
+            .set_tree(remaining_tree)
+            .set_description("feat: add a")
+            .write()
+            .expect("write remaining");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("split").expect("tx");
+
+        // The remaining commit's diff is "add a.txt", and its description
+        // was set in the same operation. Not stale.
+        assert!(matches!(
+            check_staleness(&repo, remaining.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(_)
+        ));
+    }
+
+    #[test]
+    fn squash_changing_diff_is_stale() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        // Create commit with description covering one file.
+        let t = tree(repo, &[("original.txt", "content")]);
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], t)
+            .set_description("feat: add original")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Squash new content in without updating description.
+        let t2 = tree(&repo, &[("original.txt", "content"), ("extra.txt", "extra")]);
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(t2)
+            .write()
+            .expect("squash");
+        tx.repo_mut().rebase_descendants().expect("rebase descendants");
+        let repo = tx.commit("squash").expect("tx");
+
+        // Diff changed (now includes extra.txt) but description wasn't updated.
+        let info = check_staleness(&repo, c2.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(
+            info.changed_files.iter().map(|f| f.as_internal_file_string().to_owned()).collect::<Vec<_>>(),
+            vec!["extra.txt"],
+        );
+    }
+
+    #[test]
+    fn parse_args_format_spellings() {
+        let spaced = Args::parse(["--format".to_owned(), "github-annotations".to_owned()]);
+        let inline = Args::parse(["--format=github-annotations".to_owned()]);
+        assert_eq!(
+            spaced.expect("parse").format,
+            OutputFormat::GithubAnnotations
+        );
+        assert_eq!(
+            inline.expect("parse").format,
+            OutputFormat::GithubAnnotations
+        );
+        assert!(Args::parse(["--format".to_owned()]).is_err());
+        assert!(Args::parse(["--format=bogus".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn parse_args_recognizes_doctor() {
+        let args = Args::parse(["--doctor".to_owned()]).expect("parse");
+        assert!(args.doctor);
+        assert_eq!(args.mode, Mode::Advisory);
+        assert!(Args::parse(["--bogus".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn doctor_jj_binary_matching_version_passes() {
+        let runner = FakeRunner::default().with(&["--version"], "jj 0.38.0-abc123\n");
+        let check = check_jj_binary(&runner);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(check.detail, "jj 0.38.0-abc123");
+    }
+
+    #[test]
+    fn doctor_jj_binary_other_version_warns() {
+        let runner = FakeRunner::default().with(&["--version"], "jj 0.380.0\n");
+        assert_eq!(check_jj_binary(&runner).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn doctor_jj_binary_missing_fails() {
+        assert_eq!(
+            check_jj_binary(&FakeRunner::default()).status,
+            CheckStatus::Fail
+        );
+    }
+
+    #[test]
+    fn doctor_revset_reports_candidate_count() {
+        let runner = FakeRunner::default().with_candidates(DEFAULT_REVSET, "aaaa\nbbbb\n");
+        let check = check_revset(&runner, DEFAULT_REVSET);
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.detail.contains("2 candidates"), "{}", check.detail);
+
+        assert_eq!(
+            check_revset(&FakeRunner::default(), DEFAULT_REVSET).status,
+            CheckStatus::Fail
+        );
+    }
+
+    #[test]
+    fn doctor_state_dir_writable() {
+        let dir = testutils::new_temp_dir();
+        assert_eq!(check_state_dir(dir.path()).status, CheckStatus::Pass);
+        // Created on demand, like the hooks do.
+        assert_eq!(
+            check_state_dir(&dir.path().join("missing")).status,
+            CheckStatus::Pass
+        );
+        let file = dir.path().join("file");
+        fs::write(&file, "").expect("write file");
+        assert_eq!(
+            check_state_dir(&file.join("state")).status,
+            CheckStatus::Fail
+        );
+    }
+
+    #[test]
+    fn doctor_report_labels_each_check() {
+        let report = format_doctor_report(&[
+            DoctorCheck::new("a", CheckStatus::Pass, "fine"),
+            DoctorCheck::new("b", CheckStatus::Fail, "broken"),
+        ]);
+        assert_eq!(report, "[  ok] a: fine\n[FAIL] b: broken\n");
+    }
+
+    #[test]
+    fn doctor_fails_without_jj() {
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default();
+        let loader = |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> {
+            anyhow::bail!("no repository")
+        };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+        let args = Args {
+            doctor: true,
+            ..Args::default()
+        };
+        let Output::Report { text, exit_code } = run_with(&args, &ctx).expect("run") else {
+            panic!("expected a failing report");
+        };
+        assert_eq!(exit_code, 1);
+        assert!(text.contains("[FAIL] jj binary"), "{text}");
+    }
+
+    #[test]
+    fn parallel_results_keep_candidate_order() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // A stack of undescribed (hence stale) changes.
+        let mut tx = repo.start_transaction();
+        let mut parent_id = repo.store().root_commit_id().clone();
+        let mut commit_ids = Vec::new();
+        for i in 0..16 {
+            let files: Vec<(String, String)> = (0..=i)
+                .map(|j| (format!("file{j}.txt"), format!("v{j}")))
+                .collect();
+            let files: Vec<(&str, &str)> = files
+                .iter()
+                .map(|(p, c)| (p.as_str(), c.as_str()))
+                .collect();
+            let t = tree(tx.repo().base_repo(), &files);
+            let commit = tx
+                .repo_mut()
+                .new_commit(vec![parent_id], t)
+                .write()
+                .expect("write commit");
+            parent_id = commit.id().clone();
+            commit_ids.push(commit.id().clone());
+        }
+        let repo = tx.commit("create stack").expect("commit tx");
+
+        let expected =
+            check_verdicts(&repo, &commit_ids, &CheckOptions::default(), 1).expect("sequential");
+        assert_eq!(expected.len(), commit_ids.len());
+        assert!(expected.iter().all(|v| matches!(v, Verdict::Stale(_))));
+        for _ in 0..20 {
+            let actual =
+                check_verdicts(&repo, &commit_ids, &CheckOptions::default(), 8).expect("parallel");
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn always_valid_exact_match_bypasses_drift() {
+        let test_repo = TestRepo::init();
+        let (repo, commit) = drifted_change(&test_repo.repo, "release\n");
+        let options = CheckOptions::from(
+            Config::parse(r#"always_valid_descriptions = ["release"]"#).expect("parse"),
+        );
+
+        assert!(
+            check_staleness(&repo, commit.id(), &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale()
+                .is_some()
+        );
+        assert_eq!(
+            check_staleness(&repo, commit.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::AlwaysValid)
+        );
+    }
+
+    #[test]
+    fn always_valid_regex_match_bypasses_drift() {
+        let test_repo = TestRepo::init();
+        let (repo, commit) = drifted_change(&test_repo.repo, "merge: main into feature\n");
+        let options = CheckOptions::from(
+            Config::parse(r#"always_valid_descriptions = ["regex:^merge:"]"#).expect("parse"),
+        );
+
+        assert_eq!(
+            check_staleness(&repo, commit.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::AlwaysValid)
+        );
+    }
+
+    #[test]
+    fn run_invokes_jj_root_at_most_once() {
+        // No `.jj/repo` under the root, so the run stops at repo load, after
+        // every step that needs the workspace root.
+        let dir = testutils::new_temp_dir();
+        let root = format!("{}\n", dir.path().display());
+        let runner = FakeRunner::default()
+            .with_candidates(DEFAULT_REVSET, "0123456789abcdef\n")
+            .with(&["root"], &root);
+
+        assert!(run_with(&Args::default(), &Context::live(&runner)).is_err());
+        assert_eq!(runner.call_count(&["root"]), 1);
+    }
+
+    #[test]
+    fn fork_point_base_sees_drift_below_in_stack() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        // trunk ← a (adds a.txt) ← b (adds b.txt), both described.
+        let mut tx = repo.start_transaction();
+        let trunk = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("base.txt", "base")]))
+            .set_description("trunk")
+            .write()
+            .expect("write trunk");
+        let a = tx
+            .repo_mut()
+            .new_commit(
+                vec![trunk.id().clone()],
+                tree(repo, &[("base.txt", "base"), ("a.txt", "v1")]),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write a");
+        tx.repo_mut()
+            .new_commit(
+                vec![a.id().clone()],
+                tree(
+                    repo,
+                    &[("base.txt", "base"), ("a.txt", "v1"), ("b.txt", "b")],
+                ),
+            )
+            .set_description("feat: add b")
+            .write()
+            .expect("write b");
+        let repo = tx.commit("create stack").expect("tx");
+
+        // Edit a; b is rebased on top, its own diff untouched.
+        let mut tx = repo.start_transaction();
+        tx.repo_mut()
+            .rewrite_commit(&a)
+            .set_tree(tree(&repo, &[("base.txt", "base"), ("a.txt", "v2")]))
+            .write()
+            .expect("rewrite a");
+        tx.repo_mut()
+            .rebase_descendants()
+            .expect("rebase descendants");
+        let repo = tx.commit("edit a").expect("tx");
+        let b_id = repo.view().heads().iter().next().cloned().expect("b head");
+
+        // Parent-relative: b's own diff still just adds b.txt.
+        assert!(
+            check_staleness(&repo, &b_id, &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale()
+                .is_none()
+        );
+
+        // Fork-point-relative: the stack's net content changed under b.
+        let options = CheckOptions {
+            base: FingerprintBase::ForkPoint(vec![trunk.id().clone()]),
+            ..CheckOptions::default()
+        };
+        let info = check_staleness(&repo, &b_id, &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(
+            info.changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string().to_owned())
+                .collect::<Vec<_>>(),
+            vec!["a.txt"],
+        );
+    }
+
+    #[test]
+    fn explains_at_behind_trunk() {
+        let runner = FakeRunner::default()
+            .with_candidates(DEFAULT_REVSET, "")
+            .with(&log_args("@ & ::trunk()"), "0123456789abcdef\n");
+        assert!(gather_candidates(&runner, DEFAULT_REVSET).is_empty());
+        assert_eq!(
+            explain_no_candidates(&runner, DEFAULT_REVSET),
+            "no candidates: @ is trunk() or one of its ancestors, so \
+             `trunk()..@ ~ empty()` is empty"
+        );
+    }
+
+    #[test]
+    fn explains_empty_range() {
+        let runner = FakeRunner::default()
+            .with_candidates(DEFAULT_REVSET, "")
+            .with(&log_args("@ & ::trunk()"), "");
+        assert_eq!(
+            explain_no_candidates(&runner, DEFAULT_REVSET),
+            "no candidates: `trunk()..@ ~ empty()` matched no changes"
+        );
+    }
+
+    #[test]
+    fn drift_after_describe_transition_is_high_confidence() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Created undescribed, then described, then edited.
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_description("feat: add file")
+            .write()
+            .expect("describe");
+        let repo = tx.commit("describe").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c3 = tx
+            .repo_mut()
+            .rewrite_commit(&c2)
+            .set_tree(tree(&repo, &[("file.txt", "v2")]))
+            .write()
+            .expect("edit");
+        let repo = tx.commit("edit").expect("tx");
+
+        let info = check_staleness(&repo, c3.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(info.confidence, Confidence::High);
+        assert_eq!(info.described_commit_id.as_ref(), Some(c2.id()));
+    }
+
+    #[test]
+    fn drift_without_describe_transition_is_low_confidence() {
+        let test_repo = TestRepo::init();
+        let (repo, commit) = drifted_change(&test_repo.repo, "feat: initial");
+
+        let info = check_staleness(&repo, commit.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(info.confidence, Confidence::Low);
+        // Falls back to the creation entry, which predates the edit.
+        let described = info.described_commit_id.expect("describe point");
+        assert_ne!(&described, commit.id());
+        assert_eq!(
+            repo.store()
+                .get_commit(&described)
+                .expect("get commit")
+                .description(),
+            "feat: initial"
+        );
+    }
+
+    #[test]
+    fn second_run_reports_only_newly_stale() {
+        let dir = testutils::new_temp_dir();
+        let state_file = dir.path().join("seen");
+        let stale = |ids: &[&str]| -> Vec<StalenessInfo> {
+            ids.iter()
+                .map(|id| StalenessInfo {
+                    change_id_short: (*id).to_owned(),
+                    reason: StaleReason::ContentChanged,
+                    changed_files: vec![],
+                    confidence: Confidence::High,
+                    described_commit_id: None,
+                    description: None,
+                    detail: None,
+                    line_counts: BTreeMap::new(),
+                })
+                .collect()
+        };
+        let ids = |infos: Vec<StalenessInfo>| -> Vec<String> {
+            infos.into_iter().map(|i| i.change_id_short).collect()
+        };
+        let full_ids: HashMap<String, String> = [
+            ("aaa", "aaaazzzzzzzzzzzz"),
+            ("aaaa", "aaaazzzzzzzzzzzz"),
+            ("bbb", "bbbbzzzzzzzzzzzz"),
+        ]
+        .into_iter()
+        .map(|(short, full)| (short.to_owned(), full.to_owned()))
+        .collect();
+
+        assert_eq!(
+            ids(retain_newly_stale(&state_file, stale(&["aaa"]), &full_ids)),
+            vec!["aaa"]
+        );
+        assert_eq!(
+            fs::read_to_string(&state_file).expect("read seen"),
+            "aaaazzzzzzzzzzzz"
+        );
+        assert_eq!(
+            ids(retain_newly_stale(
+                &state_file,
+                stale(&["aaa", "bbb"]),
+                &full_ids
+            )),
+            vec!["bbb"]
+        );
+        // "aaa" went clean, so it's new again when it drifts back.
+        assert!(retain_newly_stale(&state_file, stale(&["bbb"]), &full_ids).is_empty());
+        assert_eq!(
+            ids(retain_newly_stale(
+                &state_file,
+                stale(&["aaa", "bbb"]),
+                &full_ids
+            )),
+            vec!["aaa"]
+        );
+        // A longer short ID for the same change is still the same change.
+        assert!(retain_newly_stale(&state_file, stale(&["aaaa", "bbb"]), &full_ids).is_empty());
+    }
+
+    #[test]
+    fn skip_empty_check_keeps_drift_detection() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(Config {
+            skip_empty_check: true,
+            ..Config::default()
+        });
+
+        let t = tree(repo, &[("spike.txt", "wip")]);
+        let mut tx = repo.start_transaction();
+        let undescribed = tx
+            .repo_mut()
+            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+        assert_eq!(
+            check_staleness(&repo, undescribed.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+
+        let (repo, drifted) = drifted_change(&repo, "feat: add file");
+        assert!(
+            check_staleness(&repo, drifted.id(), &options)
+                .expect("check_staleness")
+                .into_stale()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn parse_args_no_empty_check() {
+        let args = Args::parse(["--no-empty-check".to_owned()]).expect("parse");
+        assert!(args.no_empty_check);
+    }
+
+    #[test]
+    fn stop_retries_reset_on_session_change() {
+        let dir = testutils::new_temp_dir();
+        let retry_file = dir.path().join("retries");
+
+        for _ in 0..MAX_STOP_RETRIES {
+            assert!(claim_stop_retry(&retry_file, "session-a", MAX_STOP_RETRIES).expect("claim"));
+        }
+        assert!(!claim_stop_retry(&retry_file, "session-a", MAX_STOP_RETRIES).expect("claim"));
+
+        // Same file, new session: the old count no longer applies.
+        assert!(claim_stop_retry(&retry_file, "session-b", MAX_STOP_RETRIES).expect("claim"));
+        assert_eq!(
+            fs::read_to_string(&retry_file).expect("read retry file"),
+            "session-b\n1"
+        );
+    }
+
+    #[test]
+    fn one_thread_runs_sequentially_on_caller() {
+        let caller = thread::current().id();
+        let items: Vec<usize> = (0..8).collect();
+
+        let sequential = map_in_order(&items, 1, |_| thread::current().id());
+        assert!(sequential.iter().all(|id| *id == caller));
+
+        let parallel = map_in_order(&items, 4, |_| thread::current().id());
+        assert!(parallel.iter().all(|id| *id != caller));
+    }
+
+    #[test]
+    fn parse_args_threads() {
+        assert_eq!(Args::default().threads, 0);
+        let args = Args::parse(["--threads=1".to_owned()]).expect("parse");
+        assert_eq!(args.threads, 1);
+        assert!(Args::parse(["--threads".to_owned(), "many".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn require_body_flags_large_subject_only_changes() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options =
+            CheckOptions::from(Config::parse("require_body_over_files = 2").expect("parse config"));
+        let large = tree(repo, &[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]);
+        let small = tree(repo, &[("a.txt", "a")]);
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let mut write = |tree, description: &str| {
+            tx.repo_mut()
+                .new_commit(vec![root_id.clone()], tree)
+                .set_description(description)
+                .write()
+                .expect("write commit")
+        };
+        let subject_only = write(large.clone(), "feat: add files");
+        let with_body = write(large, "feat: add files\n\nAdds a, b and c for the demo.\n");
+        let small_change = write(small, "feat: add a");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let info = check_staleness(&repo, subject_only.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("subject-only multi-file change should be flagged");
+        assert_eq!(info.reason, StaleReason::MissingBody);
+        assert_eq!(info.changed_files.len(), 3);
+
+        for commit in [&with_body, &small_change] {
+            assert_eq!(
+                check_staleness(&repo, commit.id(), &options)
+                    .expect("check_staleness")
+                    .into_stale(),
+                None
+            );
+        }
+        // Off by default.
+        assert_eq!(
+            check_staleness(&repo, subject_only.id(), &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_workspace_root_still_loads() {
+        let test_repo = TestRepo::init();
+        let dir = testutils::new_temp_dir();
+        let real = dir.path().join("real");
+        fs::create_dir_all(real.join(".jj")).expect("create .jj");
+        fs::write(
+            real.join(".jj").join("repo"),
+            test_repo.repo.repo_path().display().to_string(),
+        )
+        .expect("write repo pointer");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).expect("symlink");
+
+        let runner = FakeRunner::default().with(&["root"], &format!("{}\n", link.display()));
+        let ctx = RunContext::discover(&runner).expect("discover");
+        assert_eq!(
+            ctx.workspace_root,
+            fs::canonicalize(&real).expect("canonicalize")
+        );
+
+        let settings = UserSettings::from_config(StackedConfig::with_defaults()).expect("settings");
+        load_repo(&ctx.repo_path, &settings).expect("repo loads");
+    }
+
+    #[test]
+    fn uncanonicalizable_root_is_used_as_is() {
+        let runner = FakeRunner::default().with(&["root"], "/no/such/workspace\n");
+        assert_eq!(
+            discover_workspace_root(&runner).expect("root"),
+            PathBuf::from("/no/such/workspace")
+        );
+    }
+
+    #[test]
+    fn normalized_reformat_does_not_move_describe_point() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Created undescribed, described, edited, then only re-capitalized.
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_description("feat: add file\n")
+            .write()
+            .expect("describe");
+        let repo = tx.commit("describe").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c3 = tx
+            .repo_mut()
+            .rewrite_commit(&c2)
+            .set_tree(tree(&repo, &[("file.txt", "v2")]))
+            .write()
+            .expect("edit");
+        let repo = tx.commit("edit").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c4 = tx
+            .repo_mut()
+            .rewrite_commit(&c3)
+            .set_description("Feat: add  file\n")
+            .write()
+            .expect("reformat");
+        let repo = tx.commit("reformat").expect("tx");
+
+        // By default the reformat counts as a describe and hides the drift.
+        assert_eq!(
+            check_staleness(&repo, c4.id(), &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+
+        let options = CheckOptions::from(
+            Config::parse("normalize_descriptions = true").expect("parse config"),
+        );
+        let info = check_staleness(&repo, c4.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("drift since the real describe");
+        assert_eq!(info.described_commit_id.as_ref(), Some(c2.id()));
+    }
+
+    #[test]
+    fn print_candidates_shows_overridden_revset() {
+        let config = Config::parse("revset = 'mine() ~ empty()'").expect("parse config");
+        let runner = FakeRunner::default().with_candidates("mine() ~ empty()", "abc123\ndef456\n");
+        assert_eq!(
+            format_candidates(&runner, config.revset()),
+            "revset: (mine() ~ empty()) & mutable()\nabc123\ndef456\n"
+        );
+        assert!(
+            Args::parse(["--print-candidates".to_owned()])
+                .expect("parse")
+                .print_candidates
+        );
+    }
+
+    #[test]
+    fn rev_resolves_change_id() {
+        let runner = FakeRunner::default().with_candidates("kxqpmnrl", "0123abcd\n");
+        assert_eq!(
+            resolve_rev(&runner, "kxqpmnrl", &mut Vec::new()).expect("resolve"),
+            vec!["0123abcd"]
+        );
+    }
+
+    #[test]
+    fn rev_skips_immutable_commit() {
+        // `main` resolves, but `mutable()` filters it out.
+        let runner = FakeRunner::default()
+            .with(&log_args("main"), "0123abcd\n")
+            .with_candidates("main", "");
+        assert!(
+            resolve_rev(&runner, "main", &mut Vec::new())
+                .expect("resolve")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rev_picks_working_copy_reachable_divergent_commit() {
+        let runner = FakeRunner::default()
+            .with_candidates("change_id(kxqpmnrl)", "aaaa\nbbbb\n")
+            .with_candidates("change_id(kxqpmnrl) & ::@", "bbbb\n");
+        let mut notes = Vec::new();
+        assert_eq!(
+            resolve_rev(&runner, "kxqpmnrl", &mut notes).expect("resolve"),
+            vec!["bbbb"]
+        );
+        assert_eq!(
+            notes,
+            ["note: change kxqpmnrl is divergent (2 commits: aaaa, bbbb); checking bbbb"]
+        );
+        // Not a change id: the revset error stands.
+        assert!(resolve_rev(&FakeRunner::default(), "main@origin", &mut notes).is_err());
+    }
+
+    #[test]
+    fn profile_lines_only_when_enabled() {
+        let notes = RefCell::default();
+        let mut profile = Profile::new(true, &notes);
+        assert_eq!(profile.time("load_repo", || 42), 42);
+        profile.time("check_candidates", || ());
+        let out = profile.render();
+        let phases: Vec<_> = out
+            .lines()
+            .map(|line| line.rsplit_once(": ").expect("timing").0)
+            .collect();
+        assert_eq!(
+            phases,
+            vec!["profile: load_repo", "profile: check_candidates"]
+        );
+        assert!(out.lines().all(|line| line.ends_with("ms")));
+
+        drop(profile);
+        assert_eq!(notes.borrow().len(), 2);
+        let mut disabled = Profile::new(false, &notes);
+        disabled.time("load_repo", || ());
+        assert_eq!(disabled.render(), "");
+        drop(disabled);
+        assert_eq!(notes.borrow().len(), 2);
+        assert!(
+            Args::parse(["--profile".to_owned()])
+                .expect("parse")
+                .profile
+        );
+    }
+
+    #[test]
+    fn warn_only_reasons_do_not_block() {
+        let info = |reason| StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason,
+            changed_files: vec![],
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let config = Config::parse("[severity]\nmissing_body = 'warn'").expect("parse config");
+
+        assert!(!should_block(&[info(StaleReason::MissingBody)], &config));
+        assert!(should_block(
+            &[
+                info(StaleReason::MissingBody),
+                info(StaleReason::ContentChanged)
+            ],
+            &config
+        ));
+        // Everything blocks by default.
+        assert!(should_block(
+            &[info(StaleReason::MissingBody)],
+            &Config::default()
+        ));
+    }
+
+    #[test]
+    fn changed_path_in_both_fingerprints_listed_once() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        let mut tx = repo.start_transaction();
+        let before = tx
+            .repo_mut()
+            .new_commit(
+                vec![root_id.clone()],
+                tree(repo, &[("file.txt", "v1"), ("gone.txt", "x")]),
+            )
+            .write()
+            .expect("write before");
+        let after = tx
+            .repo_mut()
+            .new_commit(
+                vec![root_id],
+                tree(repo, &[("file.txt", "v2"), ("new.txt", "y")]),
+            )
+            .write()
+            .expect("write after");
+        let repo = tx.commit("create").expect("tx");
+
+        let base = FingerprintBase::Parent;
+        let config = Config::default();
+        let described =
+            commit_diff_fingerprint(&repo, &before, &base, &config).expect("fingerprint");
+        let current = commit_diff_fingerprint(&repo, &after, &base, &config).expect("fingerprint");
+        let changed: Vec<_> = diff_fingerprint_changes(&described, &current)
+            .iter()
+            .map(|p| p.as_internal_file_string().to_owned())
+            .collect();
+        assert_eq!(changed, vec!["file.txt", "gone.txt", "new.txt"]);
+    }
+
+    #[test]
+    fn current_description_included_when_enabled() {
+        let test_repo = TestRepo::init();
+        let (repo, commit) = drifted_change(&test_repo.repo, "feat: initial\n\nDetails.\n");
+
+        let info = check_staleness(&repo, commit.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(info.description, None);
+
+        let options = |chars: usize| {
+            CheckOptions::from(Config {
+                include_description_chars: Some(chars),
+                ..Config::default()
+            })
+        };
+        let info = check_staleness(&repo, commit.id(), &options(100))
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(
+            info.description.as_deref(),
+            Some("feat: initial\n\nDetails.")
+        );
+        assert!(
+            format_stale_change(&info, &Messages::default())
+                .ends_with("\n  Current description: feat: initial\n    \n    Details.")
+        );
+
+        let info = check_staleness(&repo, commit.id(), &options(4))
+            .expect("check_staleness")
+            .into_stale()
+            .expect("should be stale");
+        assert_eq!(info.description.as_deref(), Some("feat…"));
+    }
+
+    #[test]
+    fn banned_phrase_flagged_case_insensitively() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(
+            Config::parse("banned_phrases = ['asdf', 'fix stuff']").expect("parse config"),
+        );
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let banned = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_description("Fix Stuff in the parser")
+            .write()
+            .expect("write commit");
+        let clean = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_description("fix: handle empty input in the parser")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let info = check_staleness(&repo, banned.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("banned phrase should be flagged");
+        assert_eq!(info.reason, StaleReason::BannedPhrase);
+        assert_eq!(
+            format_stale_change(&info, &Messages::default()),
+            format!(
+                "Banned phrase in description: change {} contains \"fix stuff\".",
+                info.change_id_short
+            )
+        );
+
+        assert_eq!(
+            check_staleness(&repo, clean.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+    }
+
+    #[test]
+    fn candidates_capped_with_warning() {
+        let mut candidates: Vec<String> = (0..5).map(|i| format!("{i:040x}")).collect();
+        assert_eq!(cap_candidates(&mut candidates, 5), None);
+
+        let warning = cap_candidates(&mut candidates, 3).expect("truncated");
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0], format!("{:040x}", 0));
+        assert_eq!(
+            warning,
+            "5 candidates exceed max_candidates; checking only the first 3"
+        );
+    }
+
+    #[test]
+    fn omit_files_for_empty_keeps_drift_files() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options =
+            CheckOptions::from(Config::parse("omit_files_for_empty = true").expect("parse config"));
+
+        let mut tx = repo.start_transaction();
+        let undescribed = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("a.txt", "a"), ("b.txt", "b")]),
+            )
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let info = check_staleness(&repo, undescribed.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("empty description is stale");
+        assert_eq!(info.reason, StaleReason::EmptyDescription);
+        assert!(info.changed_files.is_empty());
+        assert!(!format_stale_change(&info, &Messages::default()).contains("Changed:"));
+
+        let (repo, drifted) = drifted_change(&repo, "feat: add file");
+        let info = check_staleness(&repo, drifted.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("drift is stale");
+        assert_eq!(info.changed_files.len(), 1);
+    }
+
+    #[test]
+    fn stop_decision_carries_configured_exit_code() {
+        let dir = testutils::new_temp_dir();
+        let retry_file = dir.path().join("retries");
+        let stale = [StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: vec![],
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }];
+        let config = Config::parse("stop_exit_code = 3").expect("parse config");
+
+        match decide_stop(
+            &stale,
+            StaleCounts::of(&stale, 1),
+            &config,
+            &retry_file,
+            "session",
+        )
+        .expect("decide")
+        {
+            StopDecision::Block { exit_code, .. } => assert_eq!(exit_code, 3),
+            other => panic!("expected a block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stop_exit_code_precedence_and_range() {
+        assert_eq!(resolve_stop_exit_code(None, None), DEFAULT_STOP_EXIT_CODE);
+        assert_eq!(resolve_stop_exit_code(None, Some(3)), 3);
+        assert_eq!(resolve_stop_exit_code(Some("4"), Some(3)), 4);
+        for invalid in ["0", "126", "-1", "two"] {
+            assert_eq!(
+                resolve_stop_exit_code(Some(invalid), Some(3)),
+                DEFAULT_STOP_EXIT_CODE
+            );
+        }
+        assert_eq!(
+            resolve_stop_exit_code(None, Some(i64::MAX)),
+            DEFAULT_STOP_EXIT_CODE
+        );
+    }
+
+    #[test]
+    fn markdown_only_reformat_does_not_move_describe_point() {
+        let test_repo = TestRepo::init();
+        let (repo, describe, current) = redescribed_after_edit(
+            &test_repo.repo,
+            "feat: add file\n\nAdds the **parser**. Wires it into `main`.\n",
+            "feat: add file\n\n- Adds the parser.\n- Wires it into main.\n",
+        );
+
+        assert_eq!(
+            check_staleness(&repo, current.id(), &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+
+        let options =
+            CheckOptions::from(Config::parse("ignore_markdown = true").expect("parse config"));
+        let info = check_staleness(&repo, current.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("drift since the real describe");
+        assert_eq!(info.described_commit_id.as_ref(), Some(describe.id()));
+    }
+
+    #[test]
+    fn strip_markdown_keeps_text() {
+        assert_eq!(
+            strip_markdown("## Summary\n\n1. *First* step\n> quoted\n-not a list"),
+            "Summary First step quoted -not a list"
+        );
+    }
+
+    /// Watcher that reports scripted operation changes, then stops.
+    struct FakeWatcher(std::vec::IntoIter<&'static str>);
+
+    impl OpWatcher for FakeWatcher {
+        fn wait_for_change(&mut self) -> Result<Option<String>> {
+            Ok(self.0.next().map(str::to_owned))
+        }
+    }
+
+    #[test]
+    fn watch_rechecks_on_each_operation() {
+        let mut watcher = FakeWatcher(vec!["op1", "op2"].into_iter());
+        let mut checks = 0;
+        let rechecks = watch_loop(&mut watcher, || {
+            checks += 1;
+            // A failing check doesn't end the watch.
+            if checks == 1 {
+                bail!("transient failure");
+            }
+            Ok(())
+        })
+        .expect("watch");
+        assert_eq!(rechecks, 2);
+        assert_eq!(checks, 2);
+    }
+
+    #[test]
+    fn op_heads_listing_is_sorted() {
+        let dir = testutils::new_temp_dir();
+        for head in ["bbb", "aaa"] {
+            fs::write(dir.path().join(head), "").expect("write op head");
+        }
+        assert_eq!(read_op_heads(dir.path()), "aaa,bbb");
+        assert_eq!(read_op_heads(&dir.path().join("missing")), "");
+    }
+
+    #[test]
+    fn described_at_creation_clean_reason() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let commit = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "content")]),
+            )
+            .set_description("feat: add file")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let verdict =
+            check_staleness(&repo, commit.id(), &CheckOptions::default()).expect("check_staleness");
+        assert_eq!(verdict, Verdict::Clean(CleanReason::DescribedAtCreation));
+
+        let out = format_clean_verdicts(std::slice::from_ref(commit.id()), &[verdict]);
+        assert_eq!(
+            out,
+            format!(
+                "clean: commit {}: described at creation, never rewritten\n",
+                &commit.id().hex()[..12]
+            )
+        );
+    }
+
+    #[test]
+    fn empty_description_grace_period_uses_author_age() {
+        use jj_lib::backend::{MillisSinceEpoch, Signature};
+
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let now_millis = 1_700_000_000_000;
+        let authored = |age_secs: i64| Signature {
+            name: "Test User".to_owned(),
+            email: "test.user@example.com".to_owned(),
+            timestamp: Timestamp {
+                timestamp: MillisSinceEpoch(now_millis - age_secs * 1000),
+                tz_offset: 0,
+            },
+        };
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let fresh = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_author(authored(30))
+            .write()
+            .expect("write commit");
+        let aged = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_author(authored(600))
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let options = CheckOptions {
+            now_millis,
+            ..CheckOptions::from(
+                Config::parse("empty_grace_period_secs = 300").expect("parse config"),
+            )
+        };
+        assert_eq!(
+            check_staleness(&repo, fresh.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::WithinGracePeriod)
+        );
+        let info = check_staleness(&repo, aged.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("past the grace period");
+        assert_eq!(info.reason, StaleReason::EmptyDescription);
+    }
+
+    #[test]
+    fn ndjson_has_one_record_per_checked_change() {
+        let test_repo = TestRepo::init();
+        let (repo, stale) = drifted_change(&test_repo.repo, "feat: add file");
+
+        let mut tx = repo.start_transaction();
+        let clean = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(&repo, &[("clean.txt", "clean")]),
+            )
+            .set_description("feat: add clean file")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create clean").expect("commit tx");
+
+        let commit_ids = [stale.id().clone(), clean.id().clone()];
+        let verdicts = check_verdicts(&repo, &commit_ids, &CheckOptions::default(), 1)
+            .expect("check_verdicts");
+        let records = change_records(&repo, &commit_ids, &verdicts, DEFAULT_CHANGE_ID_LENGTH)
+            .expect("change_records");
+        let out = report::render(
+            OutputFormat::Ndjson,
+            &report::Outcome {
+                records: &records,
+                ..report::Outcome::default()
+            },
+        );
+
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("valid json line"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["commitId"], stale.id().hex());
+        assert_eq!(lines[0]["stale"], true);
+        assert_eq!(lines[0]["reason"], "contentChanged");
+        assert_eq!(lines[0]["confidence"], "low");
+        assert_eq!(lines[0]["changedFileCount"], 1);
+        assert_eq!(lines[1]["commitId"], clean.id().hex());
+        assert_eq!(lines[1]["stale"], false);
+        assert_eq!(lines[1]["cleanReason"], "describedAtCreation");
+        assert_eq!(lines[1]["changedFileCount"], 0);
+        assert_eq!(lines[1].get("reason"), None);
+    }
+
+    #[test]
+    fn oscillating_description_anchors_on_final_describe() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Created (0), described (1), cleared (2), edited (3), re-described
+        // (4), then edited again (5).
+        let mut tx = repo.start_transaction();
+        let mut commit = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write commit");
+        let mut repo = tx.commit("create").expect("commit tx");
+        let mut redescribed = None;
+        for (description, content) in [
+            (Some("feat: add file"), None),
+            (Some(""), None),
+            (None, Some("v2")),
+            (Some("feat: add file"), None),
+            (None, Some("v3")),
+        ] {
+            let mut tx = repo.start_transaction();
+            let mut builder = tx.repo_mut().rewrite_commit(&commit);
+            if let Some(description) = description {
+                builder = builder.set_description(description);
+            }
+            if let Some(content) = content {
+                builder = builder.set_tree(tree(&repo, &[("file.txt", content)]));
+            }
+            commit = builder.write().expect("rewrite");
+            repo = tx.commit("rewrite").expect("commit tx");
+            if description == Some("feat: add file") {
+                redescribed = Some(commit.id().clone());
+            }
+        }
+
+        let entries = evolog_commits(&repo, commit.id(), MAX_EVOLOG_ENTRIES).expect("evolog");
+        assert_eq!(last_describe_index(&entries, &Config::default()), Some(4));
+        let info = check_staleness(&repo, commit.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("edited since re-describe");
+        assert_eq!(info.confidence, Confidence::High);
+        assert_eq!(info.described_commit_id, redescribed);
+    }
+
+    #[test]
+    fn net_changed_files_lists_stack_diff_against_fork_point() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // trunk -> parent (adds a.txt) -> child (adds b.txt), then b.txt edited.
+        let mut tx = repo.start_transaction();
+        let trunk = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("base.txt", "base")]),
+            )
+            .set_description("trunk")
+            .write()
+            .expect("write trunk");
+        let parent = tx
+            .repo_mut()
+            .new_commit(
+                vec![trunk.id().clone()],
+                tree(repo, &[("base.txt", "base"), ("a.txt", "a")]),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write parent");
+        let child = tx
+            .repo_mut()
+            .new_commit(
+                vec![parent.id().clone()],
+                tree(
+                    repo,
+                    &[("base.txt", "base"), ("a.txt", "a"), ("b.txt", "v1")],
+                ),
+            )
+            .set_description("feat: add b")
+            .write()
+            .expect("write child");
+        let repo = tx.commit("create stack").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let child = tx
+            .repo_mut()
+            .rewrite_commit(&child)
+            .set_tree(tree(
+                &repo,
+                &[("base.txt", "base"), ("a.txt", "a"), ("b.txt", "v2")],
+            ))
+            .write()
+            .expect("rewrite child");
+        let repo = tx.commit("edit child").expect("tx");
+
+        let changed_files = |options: &CheckOptions| {
+            check_staleness(&repo, child.id(), options)
+                .expect("check_staleness")
+                .into_stale()
+                .expect("should be stale")
+                .changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        // Parent-relative: only the child's own edit.
+        assert_eq!(changed_files(&CheckOptions::default()), vec!["b.txt"]);
+
+        // Trunk-relative: everything the stack changed, each file once.
+        let options = CheckOptions {
+            base: FingerprintBase::ForkPoint(vec![trunk.id().clone()]),
+            ..CheckOptions::from(Config::parse("net_changed_files = true").expect("parse config"))
+        };
+        assert_eq!(changed_files(&options), vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn disable_env_short_circuits_everything() {
+        let runner = FakeRunner::default();
+        let argv = || ["--stop".to_owned(), "--bogus".to_owned()];
+        assert!(run_unless_disabled(Some(OsStr::new("1")), argv(), &runner).is_ok());
+        assert!(runner.calls.borrow().is_empty());
+
+        // "0" and empty leave the hook on.
+        for value in ["0", ""] {
+            assert!(run_unless_disabled(Some(OsStr::new(value)), argv(), &runner).is_err());
+        }
+    }
+
+    #[test]
+    fn filename_restating_description_flagged_when_enabled() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(
+            Config::parse("flag_filename_restatements = true").expect("parse config"),
+        );
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let restating = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("src/main.rs", "a")]))
+            .set_description("chore: update main.rs")
+            .write()
+            .expect("write commit");
+        let substantive = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("src/main.rs", "b")]))
+            .set_description("fix: handle empty input in main.rs")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let info = check_staleness(&repo, restating.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("restating description should be flagged");
+        assert_eq!(info.reason, StaleReason::RestatesFilename);
+        assert_eq!(
+            check_staleness(&repo, substantive.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::DescribedAtCreation)
+        );
+        // Off by default.
+        assert_eq!(
+            check_staleness(&repo, restating.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(CleanReason::DescribedAtCreation)
+        );
+    }
+
+    #[test]
+    fn restates_filenames_is_conservative() {
+        let paths = [RepoPathBuf::from_internal_string("src/config.rs").expect("valid path")];
+        assert!(restates_filenames("Update config.rs", &paths));
+        assert!(restates_filenames(
+            "fix(config): edit src/config.rs",
+            &paths
+        ));
+        // Nothing left after stripping verbs: not enough to judge.
+        assert!(!restates_filenames("update", &paths));
+        assert!(!restates_filenames(
+            "update config.rs\n\nThe old default was wrong.",
+            &paths
+        ));
+        assert!(!restates_filenames("update config.rs defaults", &paths));
+    }
+
+    #[test]
+    fn stop_block_lists_remediation_command_per_change() {
+        let dir = testutils::new_temp_dir();
+        let stale = |change_id: &str, files: &[&str]| StalenessInfo {
+            change_id_short: change_id.to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: files
+                .iter()
+                .map(|f| RepoPathBuf::from_internal_string(*f).expect("valid path"))
+                .collect(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let stale = [
+            stale("kxqpmnrlzwzv", &["a.txt", "b.txt"]),
+            stale("zzzzzzzzzzzz", &[]),
+        ];
+        let message = |config: &Config, retry_file: &str| match decide_stop(
+            &stale,
+            StaleCounts::of(&stale, stale.len()),
+            config,
+            &dir.path().join(retry_file),
+            "session",
+        )
+        .expect("decide")
+        {
+            StopDecision::Block { message, .. } => message,
+            other => panic!("expected a block, got {other:?}"),
+        };
+
+        let default = message(&Config::default(), "default");
+        assert!(default.contains("\n  jj describe -r kxqpmnrlzwzv  # a.txt, b.txt\n"));
+        assert!(default.contains("\n  jj describe -r zzzzzzzzzzzz\n"));
+        assert!(default.contains("active-descriptions:describe skill"));
+
+        let config =
+            Config::parse("remediation_command = 'my-vcs reword {change_id}'").expect("parse");
+        let custom = message(&config, "custom");
+        assert!(custom.contains("\n  my-vcs reword kxqpmnrlzwzv  # a.txt, b.txt\n"));
+        assert!(custom.contains("\n  my-vcs reword zzzzzzzzzzzz\n"));
+    }
+
+    #[test]
+    fn cap_message_keeps_header_within_limit() {
+        let stale: Vec<StalenessInfo> = (0..1000)
+            .map(|i| StalenessInfo {
+                change_id_short: format!("kxqpmnrl{i:04}"),
+                reason: StaleReason::ContentChanged,
+                changed_files: (0..20)
+                    .map(|f| {
+                        RepoPathBuf::from_internal_string(format!("src/module{f}/file{i}.rs"))
+                            .expect("valid path")
+                    })
+                    .collect(),
+                confidence: Confidence::High,
+                described_commit_id: None,
+                description: None,
+                detail: None,
+                line_counts: BTreeMap::new(),
+            })
+            .collect();
+        let msg = cap_message(
+            format_staleness_message(
+                &stale,
+                StaleCounts::of(&stale, stale.len()),
+                &Messages::default(),
+                None,
+            ),
+            stale.len(),
+            4000,
+        );
+        assert!(msg.len() <= 4000);
+        assert!(msg.starts_with("1000 stale changes; showing the first few.\n"));
+        assert!(msg.contains("Stale description: change kxqpmnrl0000 modified"));
+        assert!(msg.ends_with("\n(output truncated)"));
+
+        let short = format_staleness_message(
+            &stale[..1],
+            StaleCounts::of(&stale[..1], 1),
+            &Messages::default(),
+            None,
+        );
+        assert_eq!(cap_message(short.clone(), 1, 4000), short);
+
+        let dir = testutils::new_temp_dir();
+        let config = Config::parse("max_message_bytes = 2000").expect("parse config");
+        match decide_stop(
+            &stale,
+            StaleCounts::of(&stale, stale.len()),
+            &config,
+            &dir.path().join("retries"),
+            "session",
+        )
+        .expect("decide")
+        {
+            StopDecision::Block { message, .. } => {
+                assert!(message.len() <= 2000);
+                assert!(message.ends_with("then follow it for each stale change."));
+            }
+            other => panic!("expected a block, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn since_remote_sets_range_when_bookmark_exists() {
+        let config = || Config::parse("since_remote = 'main@origin'").expect("parse config");
+
+        let runner = FakeRunner::default().with(&log_args("main@origin"), "0123456789abcdef\n");
+        let mut found = config();
+        apply_since_remote(&runner, &mut found);
+        assert_eq!(found.revset(), "main@origin..@ ~ empty()");
+
+        let mut missing = config();
+        apply_since_remote(&FakeRunner::default(), &mut missing);
+        assert_eq!(missing.revset(), DEFAULT_REVSET);
+    }
+
+    #[test]
+    fn thin_subjects_flagged_when_configured() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options =
+            CheckOptions::from(Config::parse("min_subject_words = 2").expect("parse config"));
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let mut commit = |description: &str| {
+            tx.repo_mut()
+                .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", description)]))
+                .set_description(description)
+                .write()
+                .expect("write commit")
+        };
+        let bare = commit("feat:");
+        let wip = commit("feat: wip");
+        let substantive = commit("feat(fetch): retry with backoff\n\nDetails.");
+        let repo = tx.commit("create").expect("commit tx");
+
+        for thin in [&bare, &wip] {
+            let info = check_staleness(&repo, thin.id(), &options)
+                .expect("check_staleness")
+                .into_stale()
+                .expect("thin subject should be flagged");
+            assert_eq!(info.reason, StaleReason::SubjectTooThin);
+        }
+        assert_eq!(
+            check_staleness(&repo, substantive.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::DescribedAtCreation)
+        );
+    }
+
+    #[test]
+    fn subject_words_skip_prefix_and_stopwords() {
+        assert_eq!(meaningful_subject_words("feat!: the wip"), 0);
+        assert_eq!(
+            meaningful_subject_words("fix(parser): handle CRLF input"),
+            3
+        );
+        // Not a conventional type, so it counts.
+        assert_eq!(meaningful_subject_words("see: url"), 2);
+        assert_eq!(meaningful_subject_words("Note on foo: bar"), 3);
+    }
+
+    #[test]
+    fn pending_edits_defer_the_check() {
+        let config = Config::parse("require_clean_working_copy = true").expect("parse config");
+        let runner = FakeRunner::default()
+            .with(&UNSNAPSHOTTED_WC_COMMIT_ARGS, "0000\n")
+            .with(&SNAPSHOTTED_WC_COMMIT_ARGS, "1111\n");
+        assert!(defer_for_pending_edits(&runner, &config));
+
+        let settled = FakeRunner::default()
+            .with(&UNSNAPSHOTTED_WC_COMMIT_ARGS, "1111\n")
+            .with(&SNAPSHOTTED_WC_COMMIT_ARGS, "1111\n");
+        assert!(!defer_for_pending_edits(&settled, &config));
+
+        // Off by default, without spawning jj.
+        assert!(!defer_for_pending_edits(&runner, &Config::default()));
+        assert_eq!(runner.calls.borrow().len(), 2);
+    }
+
+    #[test]
+    fn unsupported_store_type_suggests_upgrading() {
+        let e = StoreLoadError::UnsupportedType {
+            store: "commit",
+            store_type: "from-the-future".to_owned(),
+        };
+        let context = repo_loader_error_context(&e);
+        let message = format!("{:#}", anyhow::Error::new(e).context(context));
+        assert!(message.starts_with(&format!(
+            "repo format newer than supported (plugin is built against jj-lib \
+             {JJ_LIB_VERSION}); upgrade the plugin: "
+        )));
+        assert!(message.contains("from-the-future"));
+    }
+
+    #[test]
+    fn file_classes_ignore_content_and_sensitive() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(
+            Config::parse(
+                "always_valid_descriptions = ['release']\n\
+                 [[file_classes]]\nglob = '*.lock'\nclass = 'ignore'\n\
+                 [[file_classes]]\nglob = 'migrations/**'\nclass = 'sensitive'\n",
+            )
+            .expect("parse config"),
+        );
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![root_id.clone()],
+                tree(repo, &[("src/a.rs", "v1"), ("Cargo.lock", "v1")]),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write commit");
+        let release = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("migrations/1.sql", "v1")]))
+            .set_description("release")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        // ignore: a lockfile-only edit isn't drift.
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(tree(&repo, &[("src/a.rs", "v1"), ("Cargo.lock", "v2")]))
+            .write()
+            .expect("rewrite");
+        let release = tx
+            .repo_mut()
+            .rewrite_commit(&release)
+            .set_tree(tree(&repo, &[("migrations/1.sql", "v2")]))
+            .write()
+            .expect("rewrite");
+        let repo = tx.commit("edit lockfile and migration").expect("commit tx");
+        assert_eq!(
+            check_staleness(&repo, c2.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+
+        // content: drift, listing only content files.
+        let mut tx = repo.start_transaction();
+        let c3 = tx
+            .repo_mut()
+            .rewrite_commit(&c2)
+            .set_tree(tree(&repo, &[("src/a.rs", "v2"), ("Cargo.lock", "v3")]))
+            .write()
+            .expect("rewrite");
+        let repo = tx.commit("edit code").expect("commit tx");
+        let info = check_staleness(&repo, c3.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("content drift");
+        assert_eq!(
+            info.changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string())
+                .collect::<Vec<_>>(),
+            vec!["src/a.rs"]
+        );
+
+        // sensitive: exemptions like always-valid descriptions don't apply.
+        let info = check_staleness(&repo, release.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("sensitive drift");
+        assert_eq!(info.reason, StaleReason::ContentChanged);
+        let unclassified = CheckOptions::from(
+            Config::parse("always_valid_descriptions = ['release']").expect("parse config"),
+        );
+        assert_eq!(
+            check_staleness(&repo, release.id(), &unclassified).expect("check_staleness"),
+            Verdict::Clean(CleanReason::AlwaysValid)
+        );
+
+        // sensitive: drift counts even below `min_changed_lines`.
+        let thresholded = CheckOptions::from(
+            Config::parse(
+                "min_changed_lines = 5\n\
+                 [[file_classes]]\nglob = 'migrations/**'\nclass = 'sensitive'\n",
+            )
+            .expect("parse config"),
+        );
+        assert_eq!(
+            check_staleness(&repo, c3.id(), &thresholded).expect("check_staleness"),
+            Verdict::Clean(CleanReason::BelowLineThreshold)
+        );
+        let info = check_staleness(&repo, release.id(), &thresholded)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("small sensitive drift");
+        assert_eq!(
+            info.changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string())
+                .collect::<Vec<_>>(),
+            vec!["migrations/1.sql"]
+        );
+    }
+
+    #[test]
+    fn run_end_to_end_with_injected_repo() {
+        let test_repo = TestRepo::init();
+        let (repo, stale) = drifted_change(&test_repo.repo, "feat: add file");
+        let workspace = testutils::new_temp_dir();
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default()
+            .with(&["root"], &format!("{}\n", workspace.path().display()))
+            .with_candidates(DEFAULT_REVSET, &format!("{}\n", stale.id().hex()));
+        let loader = |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> { Ok(repo.clone()) };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+        let message = format!(
+            "Stale description: change {} modified since last described.\n  Changed: file.txt",
+            short_change_id(&stale)
+        );
+        let hinted = format!(
+            "{message}\n  To update: jj describe -r {}",
+            short_change_id(&stale)
+        );
+
+        // Advisory: reported once per session.
+        assert_eq!(
+            run_with(&Args::default(), &ctx).expect("advisory run"),
+            Output::Stdout(format!(
+                "{}\n",
+                serde_json::json!({ "hookSpecificOutput": { "additionalContext": hinted } })
+            ))
+        );
+        assert_eq!(
+            run_with(&Args::default(), &ctx).expect("advisory rerun"),
+            Output::Nothing
+        );
+
+        // Stop: blocks with the same report.
+        let stop = Args {
+            mode: Mode::Stop,
+            ..Args::default()
+        };
+        match run_with(&stop, &ctx).expect("stop run") {
+            Output::Block {
+                message: block,
+                exit_code,
+            } => {
+                assert_eq!(exit_code, DEFAULT_STOP_EXIT_CODE);
+                assert!(block.starts_with(&format!("{message}\n\nTo update them:\n")));
+            }
+            other => panic!("expected a block, got {other:?}"),
+        }
+
+        // Check: the plain report and exit 1, every time and without
+        // touching the stop hook's retries.
+        let check = Args {
+            mode: Mode::Check,
+            ..Args::default()
+        };
+        let retries = ctx.workspace_state_file(
+            "retries",
+            &fs::canonicalize(workspace.path()).expect("canonicalize"),
+        );
+        let retries_before = fs::read_to_string(&retries).ok();
+        for _ in 0..2 {
+            assert_eq!(
+                run_with(&check, &ctx).expect("check run"),
+                Output::Block {
+                    message: hinted.clone(),
+                    exit_code: CHECK_EXIT_CODE,
+                }
+            );
+        }
+        assert_eq!(fs::read_to_string(&retries).ok(), retries_before);
+
+        // Check with a report format: the report on stdout, still exit 1.
+        let check_count = Args {
+            mode: Mode::Check,
+            format: OutputFormat::Count,
+            ..Args::default()
+        };
+        assert_eq!(
+            run_with(&check_count, &ctx).expect("check count run"),
+            Output::Report {
+                text: "1\n".to_owned(),
+                exit_code: CHECK_EXIT_CODE,
+            }
+        );
+    }
+
+    #[test]
+    fn stack_positions_count_up_from_trunk() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let mut parent = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("base.txt", "base")]),
+            )
+            .set_description("trunk")
+            .write()
+            .expect("write trunk");
+        let trunk = parent.id().clone();
+        let mut stack = Vec::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            parent = tx
+                .repo_mut()
+                .new_commit(vec![parent.id().clone()], tree(repo, &[(name, name)]))
+                .set_description(format!("feat: add {name}"))
+                .write()
+                .expect("write commit");
+            stack.push(parent.id().clone());
+        }
+        let side = tx
+            .repo_mut()
+            .new_commit(vec![trunk.clone()], tree(repo, &[("side.txt", "side")]))
+            .write()
+            .expect("write side branch");
+        let repo = tx.commit("create stack").expect("commit tx");
+
+        let mut commit_ids = stack.clone();
+        commit_ids.push(side.id().clone());
+        let positions =
+            stack_positions(&repo, &[trunk], &stack[2], &commit_ids).expect("stack_positions");
+        for (index, commit_id) in stack.iter().enumerate() {
+            assert_eq!(
+                positions.get(commit_id),
+                Some(&report::StackPosition {
+                    position: index + 1,
+                    size: 3
+                })
+            );
+        }
+        // Off the `@` path.
+        assert_eq!(positions.get(side.id()), None);
+    }
+
+    #[test]
+    fn missing_candidate_commits_are_skipped() {
+        let test_repo = TestRepo::init();
+        let (repo, commit) = drifted_change(&test_repo.repo, "feat: add file");
+        let bogus = CommitId::new(vec![0xde; 20]);
+
+        let commit_ids = retain_existing_commits(&repo, vec![bogus, commit.id().clone()]);
+        assert_eq!(commit_ids, vec![commit.id().clone()]);
+
+        let verdicts =
+            check_verdicts(&repo, &commit_ids, &CheckOptions::default(), 1).expect("check");
+        assert_eq!(verdicts.len(), 1);
+        assert!(matches!(verdicts[0], Verdict::Stale(_)));
+    }
+
+    #[test]
+    fn ignore_empty_files_skips_empty_file_additions() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .set_description("feat: add file")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(tree(&repo, &[("file.txt", "v1"), ("__init__.py", "")]))
+            .write()
+            .expect("add empty file");
+        let repo = tx.commit("add empty file").expect("tx");
+
+        // The cache is shared, so the second run must not reuse the first's
+        // digests.
+        let cache = testutils::new_temp_dir();
+        for fingerprint_cache in [None, Some(cache.path().to_owned())] {
+            let is_stale = |config: Config| {
+                let options = CheckOptions {
+                    config,
+                    fingerprint_cache: fingerprint_cache.clone(),
+                    ..CheckOptions::default()
+                };
+                check_staleness(&repo, c2.id(), &options)
+                    .expect("check")
+                    .into_stale()
+                    .is_some()
+            };
+            assert!(is_stale(Config::default()));
+            assert!(!is_stale(Config {
+                ignore_empty_files: true,
+                ..Config::default()
+            }));
+        }
+    }
+
+    #[test]
+    fn message_template_overrides_one_reason() {
+        let config = Config::parse(
+            "[messages]\ncontent_changed = 'Redescribe {change}: {count} file(s) moved on ({files})'",
+        )
+        .expect("parse config");
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let info = check_staleness(&repo, drifted.id(), &CheckOptions::default())
+            .expect("check_staleness")
+            .into_stale()
+            .expect("drift is stale");
+        assert_eq!(
+            format_stale_change(&info, &config.messages),
+            format!(
+                "Redescribe {}: 1 file(s) moved on (file.txt)\n  Changed: file.txt",
+                info.change_id_short
+            )
+        );
+
+        let thin = StalenessInfo {
+            reason: StaleReason::SubjectTooThin,
+            changed_files: Vec::new(),
+            ..info
+        };
+        assert_eq!(
+            format_stale_change(&thin, &config.messages),
+            format_stale_change(&thin, &Messages::default())
+        );
+    }
+
+    #[test]
+    fn description_of_sibling_diff_is_flagged() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(Config {
+            flag_mismatched_descriptions: true,
+            ..Config::default()
+        });
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let parser = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("src/parser.rs", "p")]))
+            .set_description("fix: handle empty input in `src/parser.rs`")
+            .write()
+            .expect("write commit");
+        let mismatched = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("docs/guide.md", "g")]))
+            .set_description("fix: handle empty input in parser.rs")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let info = check_staleness(&repo, mismatched.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("mismatch should be flagged");
+        assert_eq!(info.reason, StaleReason::DescriptionMismatchesDiff);
+        assert_eq!(info.detail, Some(short_change_id(&parser)));
+
+        assert_eq!(
+            check_staleness(&repo, parser.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+    }
+
+    #[test]
+    fn no_reset_keeps_retry_count_on_clean_result() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let mut tx = repo.start_transaction();
+        let clean = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .set_description("feat: add file")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let workspace = testutils::new_temp_dir();
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default()
+            .with(&["root"], &format!("{}\n", workspace.path().display()))
+            .with_candidates(DEFAULT_REVSET, &format!("{}\n", clean.id().hex()));
+        let loader = |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> { Ok(repo.clone()) };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+        let retries = ctx.workspace_state_file(
+            "retries",
+            &fs::canonicalize(workspace.path()).expect("canonicalize"),
+        );
+        fs::write(&retries, "session\n2").expect("write retry file");
+
+        let no_reset = Args {
+            mode: Mode::Stop,
+            no_reset: true,
+            ..Args::default()
+        };
+        assert_eq!(run_with(&no_reset, &ctx).expect("run"), Output::Nothing);
+        assert_eq!(
+            fs::read_to_string(&retries).expect("retry file kept"),
+            "session\n2"
+        );
+
+        let stop = Args {
+            mode: Mode::Stop,
+            ..Args::default()
+        };
+        assert_eq!(run_with(&stop, &ctx).expect("run"), Output::Nothing);
+        assert!(!retries.exists());
+    }
+
+    #[test]
+    fn describe_point_strategies_pick_different_anchors() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        // Created undescribed with v1, then described and edited to v2 at once.
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_description("feat: add file")
+            .set_tree(tree(&repo, &[("file.txt", "v2")]))
+            .write()
+            .expect("describe and edit");
+        let repo = tx.commit("describe").expect("tx");
+
+        let verdict = |describe_point| {
+            let options = CheckOptions::from(Config {
+                describe_point,
+                ..Config::default()
+            });
+            check_staleness(&repo, c2.id(), &options).expect("check_staleness")
+        };
+        assert_eq!(
+            verdict(DescribePointStrategy::LastDescriptionChange),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+        let info = verdict(DescribePointStrategy::FirstEntry)
+            .into_stale()
+            .expect("changed since creation");
+        assert_eq!(info.described_commit_id, Some(c1.id().clone()));
+        assert_eq!(info.confidence, Confidence::High);
+    }
+
+    #[test]
+    fn changed_only_reports_just_drift() {
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let mut tx = repo.start_transaction();
+        let undescribed = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(&repo, &[("other.txt", "other")]),
+            )
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let workspace = testutils::new_temp_dir();
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default()
+            .with(&["root"], &format!("{}\n", workspace.path().display()))
+            .with_candidates(
+                DEFAULT_REVSET,
+                &format!("{}\n{}\n", drifted.id().hex(), undescribed.id().hex()),
+            );
+        let loader = |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> { Ok(repo.clone()) };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+        let args = Args {
+            format: OutputFormat::Text,
+            changed_only: true,
+            ..Args::default()
+        };
+        let Output::Stdout(out) = run_with(&args, &ctx).expect("run") else {
+            panic!("expected a report");
+        };
+        assert!(out.contains(&short_change_id(&drifted)));
+        assert!(!out.contains(&short_change_id(&undescribed)));
+    }
+
+    #[test]
+    fn verbose_and_profile_output_become_notes() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let mut tx = repo.start_transaction();
+        let described = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .set_description("feat: add file")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        let workspace = testutils::new_temp_dir();
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default()
+            .with(&["root"], &format!("{}\n", workspace.path().display()))
+            .with_candidates(DEFAULT_REVSET, &format!("{}\n", described.id().hex()));
+        let loader = |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> { Ok(repo.clone()) };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+        let args = Args {
+            format: OutputFormat::Text,
+            verbose: true,
+            profile: true,
+            ..Args::default()
+        };
+        run_with(&args, &ctx).expect("run");
+        let notes = ctx.notes.take();
+        let hex = described.id().hex();
+        assert!(
+            notes
+                .iter()
+                .any(|note| note.starts_with(&format!("clean: commit {}", &hex[..12]))),
+            "{notes:?}"
+        );
+        assert!(
+            notes
+                .iter()
+                .any(|note| note.starts_with("profile: check_candidates")),
+            "{notes:?}"
+        );
+    }
+
+    #[test]
+    fn changed_only_sees_drift_past_failing_lints() {
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let mut options = CheckOptions::from(Config {
+            min_description_chars: 100,
+            ..Config::default()
+        });
+        let check = |options: &CheckOptions| {
+            check_staleness(&repo, drifted.id(), options)
+                .expect("check_staleness")
+                .into_stale()
+                .expect("stale")
+                .reason
+        };
+        assert_eq!(check(&options), StaleReason::DescriptionTooShort);
+
+        options.changed_only = true;
+        assert_eq!(check(&options), StaleReason::ContentChanged);
+    }
+
+    #[test]
+    fn crlf_only_description_change_does_not_re_anchor() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let mut tx = repo.start_transaction();
+        let mut commit = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .write()
+            .expect("write commit");
+        let mut repo = tx.commit("create").expect("commit tx");
+        // Described (1), edited (2), then re-saved with CRLF line endings (3).
+        for (description, content) in [
+            (Some("feat: add file\n\nBody.\n"), None),
+            (None, Some("v2")),
+            (Some("feat: add file\r\n\r\nBody.\r\n"), None),
+        ] {
+            let mut tx = repo.start_transaction();
+            let mut builder = tx.repo_mut().rewrite_commit(&commit);
+            if let Some(description) = description {
+                builder = builder.set_description(description);
+            }
+            if let Some(content) = content {
+                builder = builder.set_tree(tree(&repo, &[("file.txt", content)]));
+            }
+            commit = builder.write().expect("rewrite");
+            repo = tx.commit("rewrite").expect("commit tx");
+        }
+
+        let entries = evolog_commits(&repo, commit.id(), MAX_EVOLOG_ENTRIES).expect("evolog");
+        assert_eq!(last_describe_index(&entries, &Config::default()), Some(1));
+        assert!(
+            check_staleness(&repo, commit.id(), &CheckOptions::default())
+                .expect("check_staleness")
+                .into_stale()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn require_pattern_flags_nonmatching_subjects() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options = CheckOptions::from(
+            Config::parse(r"require_pattern = '^[A-Z]+-\d+: '").expect("parse config"),
+        );
+
+        let mut tx = repo.start_transaction();
+        let root_id = repo.store().root_commit_id().clone();
+        let keyed = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_description("JIRA-123: handle empty input in the parser")
+            .write()
+            .expect("write commit");
+        let unkeyed = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_description("fix: handle empty input\n\nJIRA-123: in the body")
+            .write()
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
+
+        assert_eq!(
+            check_staleness(&repo, keyed.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+        let info = check_staleness(&repo, unkeyed.id(), &options)
+            .expect("check_staleness")
+            .into_stale()
+            .expect("subject without a key is flagged");
+        assert_eq!(info.reason, StaleReason::PatternMismatch);
+        assert_eq!(
+            format_stale_change(&info, &Messages::default()),
+            format!(
+                r#"Subject doesn't match the required pattern: change {} should match "^[A-Z]+-\d+: "."#,
+                info.change_id_short
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_require_pattern_fails_open() {
+        let dir = testutils::new_temp_dir();
+        fs::write(
+            dir.path().join(config::CONFIG_FILE_NAME),
+            "require_pattern = '('\n",
+        )
+        .expect("write config");
+        let config = load_config(
+            dir.path(),
+            &StackedConfig::with_defaults(),
+            &toml::Table::new(),
+        );
+        assert!(config.require_pattern.is_none());
+    }
+
+    #[test]
+    fn cached_workspace_root_skips_jj_root() {
+        let workspace = testutils::new_temp_dir();
+        fs::write(
+            workspace.path().join(config::CONFIG_FILE_NAME),
+            "cache_workspace_root = true\n",
+        )
+        .expect("write config");
+        let state = testutils::new_temp_dir();
+        let runner = FakeRunner::default()
+            .with_candidates(DEFAULT_REVSET, "0123456789abcdef\n")
+            .with(&["root"], &format!("{}\n", workspace.path().display()));
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &load_repo,
+            state_dir: state.path().to_owned(),
+            session_id: "session".to_owned(),
+            notes: RefCell::default(),
+        };
+
+        // No `.jj/repo`, so both runs stop at repo load.
+        assert!(run_with(&Args::default(), &ctx).is_err());
+        assert!(run_with(&Args::default(), &ctx).is_err());
+        assert_eq!(runner.call_count(&["root"]), 1);
+
+        let cwd = env::current_dir().expect("cwd");
+        let cache = ctx.state_file("root");
+        let later = SystemTime::now() + WORKSPACE_ROOT_CACHE_TTL;
+        assert_eq!(read_cached_root(&cache, &cwd, later), None);
+        assert_eq!(
+            read_cached_root(&cache, Path::new("/elsewhere"), SystemTime::now()),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_fd_receives_stdout_output() {
+        use std::io::Read as _;
+        use std::os::fd::AsRawFd as _;
+
+        let (mut reader, writer) = std::io::pipe().expect("pipe");
+        let fd = u32::try_from(writer.as_raw_fd()).expect("non-negative fd");
+        let output = redirect_output(Output::Stdout("2\n".to_owned()), fd);
+        assert_eq!(output, Output::Nothing);
+        drop(writer);
+        let mut text = String::new();
+        reader.read_to_string(&mut text).expect("read pipe");
+        assert_eq!(text, "2\n");
+
+        // An fd that isn't open fails open.
+        assert_eq!(
+            redirect_output(Output::Stdout("2\n".to_owned()), 9999),
+            Output::Nothing
+        );
+    }
+
+    #[test]
+    fn trivial_only_drift_warns() {
+        let drift = |files: &[&str]| StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: files
+                .iter()
+                .map(|file| RepoPathBuf::from_internal_string(*file).expect("valid path"))
+                .collect(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let config = Config::parse("trivial_paths = ['CHANGELOG.md', '*.lock']").expect("parse");
+
+        assert_eq!(severity(&drift(&["CHANGELOG.md"]), &config), Severity::Warn);
+        assert!(!should_block(
+            &[drift(&["CHANGELOG.md", "Cargo.lock"])],
+            &config
+        ));
+        assert!(should_block(
+            &[drift(&["CHANGELOG.md", "src/main.rs"])],
+            &config
+        ));
+
+        let dropped = StalenessInfo {
+            reason: StaleReason::ReferencesMissingFile,
+            ..drift(&["CHANGELOG.md"])
+        };
+        assert_eq!(severity(&dropped, &config), Severity::Warn);
+    }
+
+    #[test]
+    fn revset_env_overrides_only_when_not_blank() {
+        assert_eq!(revset_from_env(None), None);
+        assert_eq!(revset_from_env(Some("  \n")), None);
+        assert_eq!(
+            revset_from_env(Some(" mine() ~ empty() ")).as_deref(),
+            Some("mine() ~ empty()")
+        );
+
+        let mut config = Config::default();
+        apply_revset_override(None, Some("mine()"), &toml::Table::new(), &mut config);
+        assert_eq!(config.revset(), "mine()");
+        let runner = FakeRunner::default().with_candidates("mine()", "abc\n");
+        assert_eq!(gather_candidates(&runner, config.revset()), ["abc"]);
+
+        let mut config = Config::default();
+        apply_revset_override(None, None, &toml::Table::new(), &mut config);
+        assert_eq!(config.revset(), DEFAULT_REVSET);
+    }
+
+    #[test]
+    fn revset_flag_beats_env_and_composes_with_stop() {
+        let args = Args::parse(["--stop", "--revset", "mine() ~ empty()"].map(String::from))
+            .expect("parse");
+        assert_eq!(args.mode, Mode::Stop);
+        assert_eq!(args.revset.as_deref(), Some("mine() ~ empty()"));
+        let args = Args::parse(["--revset=mine()".to_owned()]).expect("parse");
+        assert_eq!(args.revset.as_deref(), Some("mine()"));
+        assert!(Args::parse(["--revset".to_owned()]).is_err());
+
+        let mut config = Config::default();
+        apply_revset_override(
+            Some("mine()"),
+            Some("all()"),
+            &toml::Table::new(),
+            &mut config,
+        );
+        assert_eq!(config.revset(), "mine()");
+    }
+
+    #[test]
+    fn whitespace_insensitive_ignores_reformatting() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("a.rs", "fn a() {}\n"), ("b.bin", "\0v1")]),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let rewrite = |repo: &Arc<ReadonlyRepo>, files: &[(&str, &str)]| {
+            let mut tx = repo.start_transaction();
+            let commit = tx
+                .repo_mut()
+                .rewrite_commit(&c1)
+                .set_tree(tree(repo, files))
+                .write()
+                .expect("rewrite");
+            (tx.commit("edit").expect("tx"), commit)
+        };
+        let is_stale = |repo: &Arc<ReadonlyRepo>, commit: &Commit, whitespace_insensitive: bool| {
+            let options = CheckOptions::from(Config {
+                whitespace_insensitive,
+                ..Config::default()
+            });
+            check_staleness(repo, commit.id(), &options)
+                .expect("check")
+                .into_stale()
+                .is_some()
+        };
+
+        // Trailing whitespace and CRLF line endings only.
+        let (reformatted_repo, reformatted) =
+            rewrite(&repo, &[("a.rs", "fn a() {}  \r\n"), ("b.bin", "\0v1")]);
+        assert!(is_stale(&reformatted_repo, &reformatted, false));
+        assert!(!is_stale(&reformatted_repo, &reformatted, true));
+
+        // Binary files still compare by value.
+        let (binary_repo, binary) = rewrite(&repo, &[("a.rs", "fn a() {}\n"), ("b.bin", "\0v1 ")]);
+        assert!(is_stale(&binary_repo, &binary, true));
+    }
+
+    #[test]
+    fn advisory_output_can_carry_stale_changes() {
+        let info = StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: vec![RepoPathBuf::from_internal_string("src/a.rs").expect("path")],
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let Output::Stdout(plain) = advisory_output("msg", &[info.clone()], false) else {
+            panic!("advisory output is stdout");
+        };
+        assert_eq!(
+            plain,
+            format!(
+                "{}\n",
+                serde_json::json!({ "hookSpecificOutput": { "additionalContext": "msg" } })
+            )
+        );
+
+        let Output::Stdout(structured) = advisory_output("msg", &[info], true) else {
+            panic!("advisory output is stdout");
+        };
+        let json: serde_json::Value = serde_json::from_str(&structured).expect("json");
+        assert_eq!(json["hookSpecificOutput"]["additionalContext"], "msg");
+        assert_eq!(
+            json["hookSpecificOutput"]["staleChanges"],
+            serde_json::json!([{
+                "changeId": "kxqpmnrlzwzv",
+                "reason": "contentChanged",
+                "changedFiles": ["src/a.rs"],
+                "confidence": "high",
+            }])
+        );
+    }
+
+    #[test]
+    fn line_counts_annotate_changed_files() {
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let info = |line_counts: bool| {
+            let options = CheckOptions::from(Config {
+                line_counts,
+                ..Config::default()
+            });
+            check_staleness(&repo, drifted.id(), &options)
+                .expect("check_staleness")
+                .into_stale()
+                .expect("drift is stale")
+        };
+        assert!(info(false).line_counts.is_empty());
+        let message = format_stale_change(&info(true), &Messages::default());
+        assert!(
+            message.ends_with("\n  Changed: file.txt (+1/-0)"),
+            "{message}"
+        );
+
+        assert_eq!(
+            count_lines(b"a\nb\nc\n", b"a\nB\nc\nd\n"),
+            LineCount::Text {
+                additions: 2,
+                deletions: 1
+            }
+        );
+    }
+
+    #[test]
+    fn min_changed_lines_tolerates_small_edits() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("a.rs", "one\ntwo\nthree\n")]),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let verdict = |contents: &str| {
+            let mut tx = repo.start_transaction();
+            let commit = tx
+                .repo_mut()
+                .rewrite_commit(&c1)
+                .set_tree(tree(&repo, &[("a.rs", contents)]))
+                .write()
+                .expect("rewrite");
+            let repo = tx.commit("edit").expect("tx");
+            let options = CheckOptions::from(Config {
+                min_changed_lines: 3,
+                ..Config::default()
+            });
+            check_staleness(&repo, commit.id(), &options).expect("check")
+        };
+        // A typo fix: one line out, one in.
+        assert_eq!(
+            verdict("one\ntwo\nthree!\n"),
+            Verdict::Clean(CleanReason::BelowLineThreshold)
+        );
+        assert!(
+            verdict("one\n2\n3\nfour\n")
+                .into_stale()
+                .is_some_and(|info| info.reason == StaleReason::ContentChanged)
+        );
+    }
+
+    #[test]
+    fn fingerprint_cache_is_reused() {
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let cache = testutils::new_temp_dir();
+        let options = CheckOptions {
+            fingerprint_cache: Some(cache.path().to_owned()),
+            ..CheckOptions::default()
+        };
+        let check = || check_staleness(&repo, drifted.id(), &options).expect("check");
+
+        assert!(check().into_stale().is_some());
+        let entries: Vec<_> = fs::read_dir(cache.path())
+            .expect("read cache dir")
+            .map(|entry| entry.expect("entry").path())
+            .collect();
+        assert_eq!(entries.len(), 2, "one entry per fingerprint");
+
+        // Equal cached fingerprints short-circuit the diff entirely.
+        for entry in &entries {
+            fs::write(entry, "{}").expect("overwrite cache entry");
+        }
+        assert_eq!(check(), Verdict::Clean(CleanReason::UnchangedSinceDescribe));
+    }
+
+    #[test]
+    fn fingerprint_cache_spares_rechecking_stale_changes() {
+        let test_repo = TestRepo::init();
+        let (repo, drifted) = drifted_change(&test_repo.repo, "feat: add file");
+        let cache = testutils::new_temp_dir();
+        let options = CheckOptions {
+            fingerprint_cache: Some(cache.path().to_owned()),
+            ..CheckOptions::default()
+        };
+        let check = || check_staleness(&repo, drifted.id(), &options).expect("check");
+        let diffs = || FINGERPRINT_DIFFS.with(std::cell::Cell::get);
+
+        let first = check();
+        assert!(first.clone().into_stale().is_some());
+        let before = diffs();
+        // A stop-hook retry: still stale, without diffing again.
+        assert_eq!(check(), first);
+        assert_eq!(diffs(), before);
+    }
+
+    #[test]
+    fn fingerprint_cache_prunes_old_entries() {
+        let cache = testutils::new_temp_dir();
+        let now = SystemTime::now();
+        let old = cache.path().join("old");
+        let fresh = cache.path().join("fresh");
+        for file in [&old, &fresh] {
+            fs::write(file, "{}").expect("write cache entry");
+        }
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .and_then(|file| file.set_modified(now - FINGERPRINT_CACHE_MAX_AGE * 2))
+            .expect("age cache entry");
+
+        prune_fingerprint_cache(cache.path(), now);
+        assert!(!old.exists());
+        assert!(fresh.exists());
+        // A missing cache dir is fine.
+        prune_fingerprint_cache(&cache.path().join("missing"), now);
+    }
+
+    #[test]
+    fn description_naming_dropped_file_is_flagged() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("src/lexer.rs", "l"), ("src/parser.rs", "p")]),
+            )
+            .set_description("feat: add lexer.rs and src/parser.rs")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(tree(&repo, &[("src/lexer.rs", "l")]))
+            .write()
+            .expect("drop parser");
+        let repo = tx.commit("drop parser").expect("tx");
+
+        let check = |flag_missing_references: bool| {
+            let options = CheckOptions::from(Config {
+                flag_missing_references,
+                ..Config::default()
+            });
+            check_staleness(&repo, c2.id(), &options)
+                .expect("check")
+                .into_stale()
+                .expect("drift is stale")
+        };
+        assert_eq!(check(false).reason, StaleReason::ContentChanged);
+        let info = check(true);
+        assert_eq!(info.reason, StaleReason::ReferencesMissingFile);
+        assert_eq!(info.detail.as_deref(), Some("src/parser.rs"));
+    }
+
+    #[test]
+    fn git_backed_repo_loads() {
+        let test_repo = TestRepo::init_with_backend(testutils::TestRepoBackend::Git);
+        let repo = &test_repo.repo;
+        let mut tx = repo.start_transaction();
+        let commit = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v1")]),
+            )
+            .set_description("feat: add file")
+            .write()
+            .expect("write");
+        tx.commit("create").expect("tx");
+
+        let settings = UserSettings::from_config(StackedConfig::with_defaults()).expect("settings");
+        let loaded = load_repo(test_repo.repo.repo_path(), &settings).expect("repo loads");
+        assert_eq!(
+            loaded
+                .store()
+                .get_commit(commit.id())
+                .expect("commit resolves")
+                .description(),
+            "feat: add file"
+        );
+    }
+
+    #[test]
+    fn check_overrides_stop_in_either_order() {
+        for argv in [["--stop", "--check"], ["--check", "--stop"]] {
+            let args = Args::parse(argv.map(String::from)).expect("parse");
+            assert_eq!(args.mode, Mode::Check);
+        }
+        assert_eq!(Args::default().mode, Mode::Advisory);
+    }
+
+    #[test]
+    fn gitignored_files_are_left_out_of_the_fingerprint() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let files = |artifact: &'static str, log: &'static str| {
+            [
+                (".gitignore", "build/\n"),
+                ("src/.gitignore", "*.log\n"),
+                ("src/a.rs", "fn a() {}"),
+                ("build/out.o", artifact),
+                ("src/debug.log", log),
+            ]
+        };
+
+        let mut tx = repo.start_transaction();
+        let c1 = tx
+            .repo_mut()
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &files("v1", "v1")),
+            )
+            .set_description("feat: add a")
+            .write()
+            .expect("write");
+        let repo = tx.commit("create").expect("tx");
+
+        // Tracked before being ignored, then rebuilt.
+        let mut tx = repo.start_transaction();
+        let c2 = tx
+            .repo_mut()
+            .rewrite_commit(&c1)
+            .set_tree(tree(&repo, &files("v2", "v2")))
+            .write()
+            .expect("rebuild");
+        let repo = tx.commit("rebuild").expect("tx");
+
+        assert_eq!(
+            check_staleness(&repo, c2.id(), &CheckOptions::default()).expect("check"),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+    }
+
+    #[test]
+    fn log_directives_read_both_variables() {
+        assert_eq!(log_directives(None, false), "jj_stale_descriptions=off");
+        assert_eq!(log_directives(None, true), "jj_stale_descriptions=debug");
+        assert_eq!(
+            log_directives(Some("debug"), false),
+            "jj_stale_descriptions=debug"
+        );
+        assert_eq!(
+            log_directives(Some("TRACE"), false),
+            "jj_stale_descriptions=trace"
+        );
+        assert_eq!(
+            log_directives(Some("trace"), true),
+            "jj_stale_descriptions=trace"
+        );
+        // A quieter level doesn't undo `ACTIVE_DESCRIPTIONS_DEBUG`.
+        assert_eq!(
+            log_directives(Some("info"), true),
+            "jj_stale_descriptions=debug"
+        );
+        assert_eq!(log_directives(Some("jj_lib=debug"), false), "jj_lib=debug");
+        assert_eq!(
+            log_directives(Some("jj_lib=debug"), true),
+            "jj_stale_descriptions=debug,jj_lib=debug"
+        );
+    }
+
+    #[test]
+    fn retry_files_are_per_workspace() {
+        let runner = FakeRunner::default();
+        let loader =
+            |_: &Path, _: &UserSettings| -> Result<Arc<ReadonlyRepo>> { bail!("not loaded") };
+        let ctx = Context {
+            runner: &runner,
+            repo_loader: &loader,
+            state_dir: PathBuf::from("/state"),
+            session_id: "unknown".to_owned(),
+            notes: RefCell::default(),
+        };
+        let a = ctx.workspace_state_file("retries", Path::new("/work/a"));
+        assert_eq!(a, ctx.workspace_state_file("retries", Path::new("/work/a")));
+        assert_ne!(a, ctx.workspace_state_file("retries", Path::new("/work/b")));
+        assert!(a.starts_with("/state"));
+
+        assert_eq!(
+            state_dir_from(Some("/custom".into())),
+            PathBuf::from("/custom")
+        );
+        assert_eq!(state_dir_from(Some("".into())), env::temp_dir());
+        assert_eq!(state_dir_from(None), env::temp_dir());
+    }
+
+    #[test]
+    fn stop_json_blocks_with_a_decision_object() {
+        let dir = testutils::new_temp_dir();
+        let retry_file = dir.path().join("retries");
+        let stale = [StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason: StaleReason::EmptyDescription,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }];
+        let config = Config {
+            stop_json: true,
+            ..Config::default()
+        };
+
+        let Output::Stdout(out) = hook_output(
+            &stale,
+            StaleCounts::of(&stale, 1),
+            Mode::Stop,
+            &config,
+            &retry_file,
+            "session",
+        )
+        .expect("stop") else {
+            panic!("expected a JSON decision");
+        };
+        let json: serde_json::Value = serde_json::from_str(&out).expect("json");
+        assert_eq!(json["decision"], "block");
+        assert!(
+            json["reason"]
+                .as_str()
+                .is_some_and(|reason| reason.contains("jj describe -r kxqpmnrlzwzv"))
+        );
+        assert_eq!(
+            fs::read_to_string(&retry_file).expect("retry file"),
+            "session\n1"
+        );
+    }
+
+    #[test]
+    fn merge_commit_verdict_survives_rebasing_a_parent() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
+
+        let mut tx = repo.start_transaction();
+        let left = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a1")]))
+            .set_description("left")
+            .write()
+            .expect("write left");
+        let right = tx
+            .repo_mut()
+            .new_commit(vec![root_id.clone()], tree(repo, &[("b.txt", "b")]))
+            .set_description("right")
+            .write()
+            .expect("write right");
+        let repo = tx.commit("create parents").expect("tx");
+
+        // The merge's own diff, against the auto-merged parents, adds m.txt.
+        let mut tx = repo.start_transaction();
+        let merge = tx
+            .repo_mut()
+            .new_commit(
+                vec![left.id().clone(), right.id().clone()],
+                tree(&repo, &[("a.txt", "a1"), ("b.txt", "b"), ("m.txt", "m")]),
+            )
+            .set_description("merge: add m.txt")
+            .write()
+            .expect("write merge");
+        let repo = tx.commit("create merge").expect("tx");
+
+        // Rebase onto a different left parent, carrying its changes along.
+        let mut tx = repo.start_transaction();
+        let new_left = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(&repo, &[("a.txt", "a2")]))
+            .set_description("left v2")
+            .write()
+            .expect("write new left");
+        let rebased = tx
+            .repo_mut()
+            .rewrite_commit(&merge)
+            .set_parents(vec![new_left.id().clone(), right.id().clone()])
+            .set_tree(tree(
+                tx.repo().base_repo(),
+                &[("a.txt", "a2"), ("b.txt", "b"), ("m.txt", "m")],
+            ))
+            .write()
+            .expect("rebase");
+        let repo = tx.commit("rebase").expect("tx");
+
+        assert_eq!(
+            check_staleness(&repo, rebased.id(), &CheckOptions::default())
+                .expect("check_staleness"),
+            Verdict::Clean(CleanReason::UnchangedSinceDescribe)
+        );
+        let options = CheckOptions {
+            config: Config {
+                skip_merges: true,
+                ..Config::default()
+            },
+            ..CheckOptions::default()
+        };
+        assert_eq!(
+            check_staleness(&repo, rebased.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::MergeSkipped)
+        );
     }
 
     #[test]
-    fn empty_description_is_stale() {
+    fn several_stale_changes_get_a_summary_line() {
+        let stale = |change_id: &str| StalenessInfo {
+            change_id_short: change_id.to_owned(),
+            reason: StaleReason::EmptyDescription,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let stale = [
+            stale("kxqpmnrlzwzv"),
+            stale("zzzzzzzzzzzz"),
+            stale("yqosqzyt"),
+        ];
+        let messages = Messages::default();
+
+        let message = format_staleness_message(&stale, StaleCounts::of(&stale, 7), &messages, None);
+        let (summary, changes) = message.split_once('\n').expect("summary line");
+        assert_eq!(summary, "3 of 7 changes have stale descriptions:");
+        assert_eq!(
+            changes,
+            stale
+                .iter()
+                .map(|info| format_stale_change(info, &messages))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        assert_eq!(
+            format_staleness_message(
+                &stale[..1],
+                StaleCounts::of(&stale[..1], 7),
+                &messages,
+                None
+            ),
+            format_stale_change(&stale[0], &messages)
+        );
+        // Advisory runs list only the newly stale, but count them all.
+        let newly_stale = format_staleness_message(
+            &stale[..1],
+            StaleCounts {
+                stale: 3,
+                checked: 7,
+            },
+            &messages,
+            None,
+        );
+        assert_eq!(
+            newly_stale,
+            format!(
+                "3 of 7 changes have stale descriptions:\n{}",
+                format_stale_change(&stale[0], &messages)
+            )
+        );
+    }
+
+    #[test]
+    fn only_mine_skips_other_authors() {
+        use jj_lib::backend::Signature;
+
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id().clone();
 
-        let t = tree(repo, &[("file.txt", "content")]);
         let mut tx = repo.start_transaction();
-        let commit = tx
+        let mine = tx
             .repo_mut()
-            .new_commit(vec![repo.store().root_commit_id().clone()], t)
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .write()
+            .expect("write commit");
+        let author = Signature {
+            name: "Build Bot".to_owned(),
+            email: "bot@example.com".to_owned(),
+            timestamp: mine.author().timestamp,
+        };
+        let theirs = tx
+            .repo_mut()
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_author(author)
             .write()
             .expect("write commit");
         let repo = tx.commit("create").expect("commit tx");
 
-        assert!(check_staleness(&repo, commit.id())
+        let options = CheckOptions {
+            user_email: Some(mine.author().email.to_uppercase()),
+            ..CheckOptions::from(Config::parse("only_mine = true").expect("parse config"))
+        };
+        let info = check_staleness(&repo, mine.id(), &options)
             .expect("check_staleness")
-            .is_some());
+            .into_stale()
+            .expect("own change is checked");
+        assert_eq!(info.reason, StaleReason::EmptyDescription);
+        assert_eq!(
+            check_staleness(&repo, theirs.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::NotMine)
+        );
     }
 
     #[test]
-    fn described_at_creation_not_stale() {
+    fn grace_seconds_uses_committer_age() {
+        use jj_lib::backend::MillisSinceEpoch;
+
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
+        let now_millis = 1_700_000_000_000;
+        let root_id = repo.store().root_commit_id().clone();
 
-        let t = tree(repo, &[("file.txt", "content")]);
         let mut tx = repo.start_transaction();
-        let commit = tx
-            .repo_mut()
-            .new_commit(vec![repo.store().root_commit_id().clone()], t)
-            .set_description("feat: add file")
-            .write()
-            .expect("write commit");
+        let mut committed = |path: &str, age_secs: i64| {
+            let builder = tx
+                .repo_mut()
+                .new_commit(vec![root_id.clone()], tree(repo, &[(path, "v")]));
+            let mut committer = builder.committer().clone();
+            committer.timestamp = Timestamp {
+                timestamp: MillisSinceEpoch(now_millis - age_secs * 1000),
+                tz_offset: 0,
+            };
+            builder
+                .set_committer(committer)
+                .write()
+                .expect("write commit")
+        };
+        let fresh = committed("a.txt", 5);
+        let aged = committed("b.txt", 120);
         let repo = tx.commit("create").expect("commit tx");
 
-        assert!(check_staleness(&repo, commit.id())
-            .expect("check_staleness")
-            .is_none());
+        let options = CheckOptions {
+            now_millis,
+            ..CheckOptions::from(Config::parse("grace_seconds = 60").expect("parse config"))
+        };
+        assert_eq!(
+            check_staleness(&repo, fresh.id(), &options).expect("check_staleness"),
+            Verdict::Clean(CleanReason::RecentlyEdited)
+        );
+        assert!(
+            check_staleness(&repo, aged.id(), &options)
+                .expect("check_staleness")
+                .into_stale()
+                .is_some()
+        );
     }
 
     #[test]
-    fn content_edit_after_describe_is_stale() {
+    fn evolog_walk_stops_at_the_describe_point() {
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
 
-        // Create commit with content + description.
-        let t = tree(repo, &[("file.txt", "v1")]);
+        // Described early, then rewritten many times with the same
+        // description.
         let mut tx = repo.start_transaction();
-        let c1 = tx
+        let mut commit = tx
             .repo_mut()
-            .new_commit(vec![repo.store().root_commit_id().clone()], t)
-            .set_description("feat: initial")
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(repo, &[("file.txt", "v0")]),
+            )
             .write()
-            .expect("write");
-        let repo = tx.commit("create").expect("tx");
+            .expect("write commit");
+        let mut repo = tx.commit("create").expect("commit tx");
+        for i in 1..=40 {
+            let mut tx = repo.start_transaction();
+            let mut builder = tx.repo_mut().rewrite_commit(&commit);
+            if i == 3 {
+                builder = builder.set_description("feat: add file");
+            } else {
+                builder = builder.set_tree(tree(&repo, &[("file.txt", &format!("v{i}"))]));
+            }
+            commit = builder.write().expect("rewrite");
+            repo = tx.commit("rewrite").expect("commit tx");
+        }
 
-        // Edit content without updating description.
-        let t2 = tree(&repo, &[("file.txt", "v2")]);
+        let config = Config::default();
+        let full = evolog_commits(&repo, commit.id(), MAX_EVOLOG_ENTRIES).expect("evolog");
+        let (full_index, full_confidence) = describe_point(&full, &config);
+        let walked = evolog_to_describe_point(&repo, commit.id(), &config).expect("evolog");
+        let (index, confidence) = describe_point(&walked, &config);
+        assert_eq!(walked.len(), 39);
+        assert!(walked.len() < full.len());
+        assert_eq!(walked[index].id(), full[full_index].id());
+        assert_eq!(confidence, full_confidence);
+
+        // Never described: the whole evolog is walked, as before.
         let mut tx = repo.start_transaction();
-        let c2 = tx
+        let undescribed = tx
             .repo_mut()
-            .rewrite_commit(&c1)
-            .set_tree(t2)
+            .new_commit(
+                vec![repo.store().root_commit_id().clone()],
+                tree(&repo, &[("other.txt", "v1")]),
+            )
             .write()
-            .expect("rewrite");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("edit").expect("tx");
+            .expect("write commit");
+        let mut repo = tx.commit("create").expect("commit tx");
+        let mut undescribed = undescribed;
+        for i in 2..=5 {
+            let mut tx = repo.start_transaction();
+            undescribed = tx
+                .repo_mut()
+                .rewrite_commit(&undescribed)
+                .set_tree(tree(&repo, &[("other.txt", &format!("v{i}"))]))
+                .write()
+                .expect("rewrite");
+            repo = tx.commit("rewrite").expect("commit tx");
+        }
+        let full = evolog_commits(&repo, undescribed.id(), MAX_EVOLOG_ENTRIES).expect("evolog");
+        let walked = evolog_to_describe_point(&repo, undescribed.id(), &config).expect("evolog");
+        assert_eq!(walked, full);
+        assert_eq!(describe_point(&walked, &config), (0, Confidence::Low));
+    }
 
-        let info = check_staleness(&repo, c2.id())
-            .expect("check_staleness")
-            .expect("should be stale");
+    #[test]
+    fn stale_changes_carry_a_describe_hint() {
+        let info = StalenessInfo {
+            change_id_short: "kxqpmnrlzwzv".to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: vec![RepoPathBuf::from_internal_string("a.txt").expect("valid path")],
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let messages = Messages::default();
+
+        let message = format_staleness_message(
+            std::slice::from_ref(&info),
+            StaleCounts::of(std::slice::from_ref(&info), 1),
+            &messages,
+            Some(config::DEFAULT_REMEDIATION_COMMAND),
+        );
         assert_eq!(
-            info.changed_files.iter().map(|f| f.as_internal_file_string().to_owned()).collect::<Vec<_>>(),
-            vec!["file.txt"],
+            message,
+            format!(
+                "{}\n  To update: jj describe -r kxqpmnrlzwzv",
+                format_stale_change(&info, &messages)
+            )
+        );
+        let info = [info];
+        assert!(
+            !format_staleness_message(&info, StaleCounts::of(&info, 1), &messages, None)
+                .contains("To update")
         );
     }
 
     #[test]
-    fn describe_after_content_edit_not_stale() {
+    fn root_commit_is_never_stale() {
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
+        let root_id = repo.store().root_commit_id();
+        for config in [
+            "",
+            "min_subject_words = 3",
+            "flag_mismatched_descriptions = true",
+        ] {
+            let options = CheckOptions::from(Config::parse(config).expect("parse config"));
+            assert_eq!(
+                check_staleness(repo, root_id, &options).expect("check_staleness"),
+                Verdict::Clean(CleanReason::Immutable)
+            );
+        }
+        assert_eq!(
+            candidate_revset("::@"),
+            "(::@) & mutable()",
+            "candidates exclude immutable commits and the root"
+        );
+    }
 
-        // Create commit with content.
-        let t = tree(repo, &[("file.txt", "v1")]);
-        let mut tx = repo.start_transaction();
-        let c1 = tx
-            .repo_mut()
-            .new_commit(vec![repo.store().root_commit_id().clone()], t)
-            .set_description("feat: initial")
-            .write()
-            .expect("write");
-        let repo = tx.commit("create").expect("tx");
+    #[test]
+    fn non_conventional_subject_is_its_own_reason() {
+        let test_repo = TestRepo::init();
+        let repo = &test_repo.repo;
+        let options =
+            CheckOptions::from(Config::parse("require_conventional = true").expect("parse config"));
 
-        // Edit content.
-        let t2 = tree(&repo, &[("file.txt", "v2")]);
         let mut tx = repo.start_transaction();
-        let c2 = tx
+        let root_id = repo.store().root_commit_id().clone();
+        let conventional = tx
             .repo_mut()
-            .rewrite_commit(&c1)
-            .set_tree(t2)
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_description("fix(parser): handle empty input")
             .write()
-            .expect("rewrite");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("edit content").expect("tx");
-
-        // Update description to match.
-        let mut tx = repo.start_transaction();
-        let c3 = tx
+            .expect("write commit");
+        let plain = tx
             .repo_mut()
-            .rewrite_commit(&c2)
-            .set_description("feat: updated")
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_description("Handle empty input in the parser")
             .write()
-            .expect("describe");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("describe").expect("tx");
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
 
-        assert!(check_staleness(&repo, c3.id())
+        assert_eq!(
+            check_staleness(&repo, conventional.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
+        let info = check_staleness(&repo, plain.id(), &options)
             .expect("check_staleness")
-            .is_none());
+            .into_stale()
+            .expect("plain subject is flagged");
+        assert_eq!(info.reason, StaleReason::NonConventionalFormat);
+        assert!(
+            format_stale_change(&info, &Messages::default()).starts_with(&format!(
+                "Subject isn't a Conventional Commit: change {}",
+                info.change_id_short
+            ))
+        );
     }
 
     #[test]
-    fn rebase_without_content_change_not_stale() {
+    fn short_descriptions_are_flagged() {
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
-        let root_id = repo.store().root_commit_id().clone();
-
-        // Create parent commit.
-        let parent_tree = tree(repo, &[("base.txt", "base")]);
-        let mut tx = repo.start_transaction();
-        let parent = tx
-            .repo_mut()
-            .new_commit(vec![root_id.clone()], parent_tree)
-            .set_description("base")
-            .write()
-            .expect("write parent");
-        let repo = tx.commit("create parent").expect("tx");
-
-        // Create child commit with description.
-        let child_tree = tree(&repo, &[("base.txt", "base"), ("feat.txt", "feature")]);
-        let mut tx = repo.start_transaction();
-        let child = tx
-            .repo_mut()
-            .new_commit(vec![parent.id().clone()], child_tree)
-            .set_description("feat: add feature")
-            .write()
-            .expect("write child");
-        let repo = tx.commit("create child").expect("tx");
+        let options =
+            CheckOptions::from(Config::parse("min_description_chars = 15").expect("parse config"));
 
-        // Simulate rebase: change parent but keep same diff (feat.txt added).
-        let new_parent_tree =
-            tree(&repo, &[("base.txt", "base"), ("other.txt", "other")]);
         let mut tx = repo.start_transaction();
-        let new_parent = tx
+        let root_id = repo.store().root_commit_id().clone();
+        let short = tx
             .repo_mut()
-            .new_commit(vec![root_id.clone()], new_parent_tree)
-            .set_description("base v2")
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_description("  fix parser  \n")
             .write()
-            .expect("write new parent");
-
-        // Rebased child: new parent, but still adds feat.txt.
-        let rebased_tree = tree(
-            tx.repo().base_repo(),
-            &[
-                ("base.txt", "base"),
-                ("other.txt", "other"),
-                ("feat.txt", "feature"),
-            ],
-        );
-        let rebased = tx
+            .expect("write commit");
+        let long = tx
             .repo_mut()
-            .rewrite_commit(&child)
-            .set_parents(vec![new_parent.id().clone()])
-            .set_tree(rebased_tree)
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_description("fix: handle empty input")
             .write()
-            .expect("rebase");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("rebase").expect("tx");
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
 
-        // Diff is still just "add feat.txt" → not stale.
-        assert!(check_staleness(&repo, rebased.id())
+        let info = check_staleness(&repo, short.id(), &options)
             .expect("check_staleness")
-            .is_none());
+            .into_stale()
+            .expect("short description is flagged");
+        assert_eq!(info.reason, StaleReason::DescriptionTooShort);
+        assert_eq!(
+            format_stale_change(&info, &Messages::default()),
+            format!(
+                "Description too short: change {} needs at least 15 characters.",
+                info.change_id_short
+            )
+        );
+        assert_eq!(
+            check_staleness(&repo, long.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
     }
 
     #[test]
-    fn split_preserving_diff_not_stale() {
+    fn placeholder_descriptions_are_flagged() {
         let test_repo = TestRepo::init();
         let repo = &test_repo.repo;
-        let root_id = repo.store().root_commit_id().clone();
-
-        // Create commit with two files and a description.
-        let t = tree(repo, &[("a.txt", "aaa"), ("b.txt", "bbb")]);
-        let mut tx = repo.start_transaction();
-        let original = tx
-            .repo_mut()
-            .new_commit(vec![root_id.clone()], t)
-            .set_description("feat: add a and b")
-            .write()
-            .expect("write");
-        let repo = tx.commit("create").expect("tx");
+        let options = CheckOptions::from(
+            Config::parse(r#"placeholder_descriptions = ["wip", "tmp"]"#).expect("parse config"),
+        );
 
-        // Simulate split: first commit gets b.txt, second (rewrite of
-        // original) gets a.txt but is reparented onto first.
-        let first_tree = tree(&repo, &[("b.txt", "bbb")]);
         let mut tx = repo.start_transaction();
-        let first = tx
+        let root_id = repo.store().root_commit_id().clone();
+        let placeholder = tx
             .repo_mut()
-            .new_commit(vec![root_id.clone()], first_tree)
-            .set_description("feat: add b")
+            .new_commit(vec![root_id.clone()], tree(repo, &[("a.txt", "a")]))
+            .set_description("WIP\n")
             .write()
-            .expect("write first");
-
-        // Remaining commit: reparented, full tree includes parent's b.txt.
-        let remaining_tree =
-            tree(tx.repo().base_repo(), &[("a.txt", "aaa"), ("b.txt", "bbb")]);
-        let remaining = tx
+            .expect("write commit");
+        let described = tx
             .repo_mut()
-            .rewrite_commit(&original)
-            .set_parents(vec![first.id().clone()])
-            .set_tree(remaining_tree)
-            .set_description("feat: add a")
+            .new_commit(vec![root_id], tree(repo, &[("b.txt", "b")]))
+            .set_description("fix: drop the tmp directory after tests")
             .write()
-            .expect("write remaining");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("split").expect("tx");
+            .expect("write commit");
+        let repo = tx.commit("create").expect("commit tx");
 
-        // The remaining commit's diff is "add a.txt", and its description
-        // was set in the same operation. Not stale.
-        assert!(check_staleness(&repo, remaining.id())
+        let info = check_staleness(&repo, placeholder.id(), &options)
             .expect("check_staleness")
-            .is_none());
+            .into_stale()
+            .expect("placeholder is flagged");
+        assert_eq!(info.reason, StaleReason::PlaceholderDescription);
+        assert_eq!(info.detail.as_deref(), Some("wip"));
+        assert_eq!(
+            check_staleness(&repo, described.id(), &options)
+                .expect("check_staleness")
+                .into_stale(),
+            None
+        );
     }
 
     #[test]
-    fn squash_changing_diff_is_stale() {
-        let test_repo = TestRepo::init();
-        let repo = &test_repo.repo;
-        let root_id = repo.store().root_commit_id().clone();
-
-        // Create commit with description covering one file.
-        let t = tree(repo, &[("original.txt", "content")]);
-        let mut tx = repo.start_transaction();
-        let c1 = tx
-            .repo_mut()
-            .new_commit(vec![root_id.clone()], t)
-            .set_description("feat: add original")
-            .write()
-            .expect("write");
-        let repo = tx.commit("create").expect("tx");
+    fn duplicate_stale_changes_merge_their_files() {
+        let info = |change_id: &str, files: &[&str]| StalenessInfo {
+            change_id_short: change_id.to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: files
+                .iter()
+                .map(|f| RepoPathBuf::from_internal_string(*f).expect("valid path"))
+                .collect(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let merged = merge_duplicate_changes(vec![
+            info("kxqpmnrlzwzv", &["b.txt", "c.txt"]),
+            info("zzzzzzzzzzzz", &["z.txt"]),
+            info("kxqpmnrlzwzv", &["a.txt", "b.txt"]),
+        ]);
+        assert_eq!(
+            merged,
+            [
+                info("kxqpmnrlzwzv", &["a.txt", "b.txt", "c.txt"]),
+                info("zzzzzzzzzzzz", &["z.txt"]),
+            ]
+        );
+    }
 
-        // Squash new content in without updating description.
-        let t2 = tree(&repo, &[("original.txt", "content"), ("extra.txt", "extra")]);
-        let mut tx = repo.start_transaction();
-        let c2 = tx
-            .repo_mut()
-            .rewrite_commit(&c1)
-            .set_tree(t2)
-            .write()
-            .expect("squash");
-        tx.repo_mut().rebase_descendants().expect("rebase descendants");
-        let repo = tx.commit("squash").expect("tx");
+    #[test]
+    fn change_ids_grow_past_shared_prefixes() {
+        let ids = [
+            "kxqpmnrlzwzvtuyw".to_owned(),
+            "kxqpmnrlsmmotwvn".to_owned(),
+            "yqosqzytrlsw".to_owned(),
+        ];
+        assert_eq!(unambiguous_change_id_len(&ids, 4), 9);
+        assert_eq!(unambiguous_change_id_len(&ids, 12), 12);
+        assert_eq!(unambiguous_change_id_len(&ids[2..], 4), 4);
 
-        // Diff changed (now includes extra.txt) but description wasn't updated.
-        let info = check_staleness(&repo, c2.id())
+        let test_repo = TestRepo::init();
+        let (repo, stale) = drifted_change(&test_repo.repo, "feat: add file");
+        let options = CheckOptions {
+            change_id_len: Some(unambiguous_change_id_len(&ids, 4)),
+            ..CheckOptions::default()
+        };
+        let info = check_staleness(&repo, stale.id(), &options)
             .expect("check_staleness")
-            .expect("should be stale");
+            .into_stale()
+            .expect("drift is stale");
+        assert_eq!(info.change_id_short, change_id_prefix(&stale, 9));
+        assert_eq!(info.change_id_short.len(), 9);
+    }
+
+    #[test]
+    fn empty_and_drifted_changes_get_distinct_headlines() {
+        let info = |change_id: &str, reason| StalenessInfo {
+            change_id_short: change_id.to_owned(),
+            reason,
+            changed_files: Vec::new(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        };
+        let stale = [
+            info("kxqpmnrlzwzv", StaleReason::EmptyDescription),
+            info("zzzzzzzzzzzz", StaleReason::ContentChanged),
+        ];
+        let message = format_staleness_message(
+            &stale,
+            StaleCounts::of(&stale, 2),
+            &Messages::default(),
+            None,
+        );
         assert_eq!(
-            info.changed_files.iter().map(|f| f.as_internal_file_string().to_owned()).collect::<Vec<_>>(),
-            vec!["extra.txt"],
+            message,
+            "2 of 2 changes have stale descriptions:\n\
+             Missing description: change kxqpmnrlzwzv has no description.\n\
+             Stale description: change zzzzzzzzzzzz modified since last described."
         );
     }
 }
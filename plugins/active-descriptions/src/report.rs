@@ -0,0 +1,647 @@
+//! Report formats selected with `--format`, for consumers other than the
+//! Claude Code hook protocol (CI logs, scripts).
+
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use jj_lib::backend::CommitId;
+use jj_lib::object_id::ObjectId as _;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DEFAULT_REMEDIATION_COMMAND, Messages};
+use crate::{CleanReason, Confidence, StaleReason, StalenessInfo, Verdict};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Claude Code hook protocol (advisory JSON or stop-hook block).
+    #[default]
+    Hook,
+    /// Human-readable summary, as `--watch` prints.
+    Text,
+    /// GitHub Actions `::warning` workflow commands, one per stale change.
+    GithubAnnotations,
+    /// A single JSON [`Report`] object.
+    Json,
+    /// JUnit XML with one test case per candidate, failing the stale ones.
+    Junit,
+    /// One JSON [`ChangeRecord`] per line for every candidate, clean ones
+    /// included, for loading audits into data tools.
+    Ndjson,
+    /// Every changed file across all stale changes, sorted and deduplicated,
+    /// one per line, for feeding into `jj diff` or review scripts.
+    PlainFiles,
+    /// One line of counts by reason, for status bars and prompts.
+    Summary,
+    /// A TOML [`Results`] document, for tools that prefer TOML to JSON.
+    Toml,
+    /// Just the number of stale changes, for shell conditionals.
+    Count,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hook" => Ok(Self::Hook),
+            "text" => Ok(Self::Text),
+            "github-annotations" => Ok(Self::GithubAnnotations),
+            "json" => Ok(Self::Json),
+            "junit" => Ok(Self::Junit),
+            "ndjson" => Ok(Self::Ndjson),
+            "plain-files" => Ok(Self::PlainFiles),
+            "summary" => Ok(Self::Summary),
+            "toml" => Ok(Self::Toml),
+            "count" => Ok(Self::Count),
+            other => bail!("unknown format: {other}"),
+        }
+    }
+}
+
+/// Machine-readable results, shared by the structured formats.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub stale_changes: Vec<StaleChange>,
+    /// Whether candidates were dropped to honor `max_candidates`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// `--format toml` output: a [`Report`] with counts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Results {
+    pub summary: Summary,
+    pub report: Report,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub stale: usize,
+    pub checked: usize,
+}
+
+impl Results {
+    pub fn new(outcome: &Outcome<'_>) -> Self {
+        Self {
+            summary: Summary {
+                stale: outcome.stale.len(),
+                checked: outcome.checked.len(),
+            },
+            report: Report::new(outcome),
+        }
+    }
+}
+
+/// One stale change in a [`Report`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaleChange {
+    pub change_id: String,
+    pub reason: StaleReason,
+    pub changed_files: Vec<String>,
+    pub confidence: Confidence,
+    /// Full hex id of the commit the description was written against; diff
+    /// it against its parent to see what the description describes. Absent
+    /// for empty descriptions. A `low` [`confidence`](Self::confidence) marks
+    /// a fallback to the oldest evolog entry inspected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub described_commit_id: Option<String>,
+    /// The current description, when `include_description_chars` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Reason-specific detail, e.g. the banned phrase that matched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// 1-based position on the stack up to `@`, counting from trunk, when
+    /// `stack_positions` is set. Absent for changes off that path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_position: Option<usize>,
+    /// Number of changes on that stack.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_size: Option<usize>,
+}
+
+/// Where a change sits on the stack up to `@`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackPosition {
+    /// 1-based, counting up from trunk.
+    pub position: usize,
+    pub size: usize,
+}
+
+impl Report {
+    pub fn new(outcome: &Outcome<'_>) -> Self {
+        let stale_changes = outcome
+            .stale
+            .iter()
+            .map(|info| {
+                let mut change = StaleChange::from(info);
+                if let Some((_, stack)) = outcome
+                    .stack
+                    .iter()
+                    .find(|(change_id, _)| *change_id == info.change_id_short)
+                {
+                    change.stack_position = Some(stack.position);
+                    change.stack_size = Some(stack.size);
+                }
+                change
+            })
+            .collect();
+        Self {
+            stale_changes,
+            truncated: outcome.truncated,
+        }
+    }
+}
+
+impl From<&StalenessInfo> for StaleChange {
+    fn from(info: &StalenessInfo) -> Self {
+        Self {
+            change_id: info.change_id_short.clone(),
+            reason: info.reason,
+            changed_files: info
+                .changed_files
+                .iter()
+                .map(|f| f.as_internal_file_string().to_owned())
+                .collect(),
+            confidence: info.confidence,
+            described_commit_id: info.described_commit_id.as_ref().map(|id| id.hex()),
+            description: info.description.clone(),
+            detail: info.detail.clone(),
+            stack_position: None,
+            stack_size: None,
+        }
+    }
+}
+
+/// One checked candidate in `--format ndjson`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeRecord {
+    pub change_id: String,
+    pub commit_id: String,
+    pub stale: bool,
+    /// Why the change is stale; absent when clean.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<StaleReason>,
+    /// Why the change is clean; absent when stale.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clean_reason: Option<CleanReason>,
+    /// Files reported as changed; `0` when clean.
+    pub changed_file_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+}
+
+impl ChangeRecord {
+    pub fn new(change_id: String, commit_id: &CommitId, verdict: &Verdict) -> Self {
+        let (reason, clean_reason, changed_file_count, confidence) = match verdict {
+            Verdict::Clean(reason) => (None, Some(*reason), 0, None),
+            Verdict::Stale(info) => (
+                Some(info.reason),
+                None,
+                info.changed_files.len(),
+                Some(info.confidence),
+            ),
+        };
+        Self {
+            change_id,
+            commit_id: commit_id.hex(),
+            stale: clean_reason.is_none(),
+            reason,
+            clean_reason,
+            changed_file_count,
+            confidence,
+        }
+    }
+}
+
+/// What a run found: the input to [`render`].
+#[derive(Debug, Clone, Copy)]
+pub struct Outcome<'a> {
+    pub stale: &'a [StalenessInfo],
+    /// Short change IDs of every candidate checked, in candidate order.
+    pub checked: &'a [String],
+    /// Per-candidate records, filled in only for [`OutputFormat::Ndjson`].
+    pub records: &'a [ChangeRecord],
+    /// Stack positions by short change ID, with `stack_positions`.
+    pub stack: &'a [(String, StackPosition)],
+    /// Whether candidates were dropped to honor `max_candidates`.
+    pub truncated: bool,
+    /// Templates for the human-readable message of each stale change.
+    pub messages: &'a Messages,
+    /// The template for each stale change's suggested command.
+    pub remediation_command: &'a str,
+}
+
+impl Default for Outcome<'_> {
+    fn default() -> Self {
+        static BUILT_IN: Messages = Messages::BUILT_IN;
+        Self {
+            stale: &[],
+            checked: &[],
+            records: &[],
+            stack: &[],
+            truncated: false,
+            messages: &BUILT_IN,
+            remediation_command: DEFAULT_REMEDIATION_COMMAND,
+        }
+    }
+}
+
+/// Renders `outcome` in `format`. Only `github-annotations` and
+/// `plain-files` render nothing when nothing is stale; `ndjson` still has a
+/// record per candidate and the other formats report the empty result.
+pub fn render(format: OutputFormat, outcome: &Outcome<'_>) -> String {
+    match format {
+        OutputFormat::Hook => crate::format_staleness_message(
+            outcome.stale,
+            crate::StaleCounts::of(outcome.stale, outcome.checked.len()),
+            outcome.messages,
+            Some(outcome.remediation_command),
+        ),
+        OutputFormat::Text if outcome.stale.is_empty() => "No stale descriptions.\n".to_owned(),
+        OutputFormat::Text => format!(
+            "{}\n",
+            crate::format_staleness_message(
+                outcome.stale,
+                crate::StaleCounts::of(outcome.stale, outcome.checked.len()),
+                outcome.messages,
+                Some(outcome.remediation_command),
+            )
+        ),
+        OutputFormat::GithubAnnotations => github_annotations(outcome.stale, outcome.messages),
+        OutputFormat::Json => format!("{}\n", serde_json::json!(Report::new(outcome))),
+        OutputFormat::Junit => junit(outcome),
+        OutputFormat::Ndjson => outcome
+            .records
+            .iter()
+            .map(|record| format!("{}\n", serde_json::json!(record)))
+            .collect(),
+        OutputFormat::PlainFiles => plain_files(outcome.stale),
+        OutputFormat::Summary => summary(outcome),
+        OutputFormat::Toml => toml::to_string(&Results::new(outcome)).unwrap_or_else(|e| {
+            tracing::debug!("failed to serialize TOML report: {e}");
+            String::new()
+        }),
+        OutputFormat::Count => format!("{}\n", outcome.stale.len()),
+    }
+}
+
+/// E.g. `active-descriptions: 2 stale (1 empty, 1 drift) / 7 checked`.
+fn summary(outcome: &Outcome<'_>) -> String {
+    let mut counts: Vec<(StaleReason, usize)> = Vec::new();
+    for info in outcome.stale {
+        match counts.iter_mut().find(|(reason, _)| *reason == info.reason) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((info.reason, 1)),
+        }
+    }
+    counts.sort_by_key(|&(reason, _)| reason as u8);
+
+    let mut line = format!("active-descriptions: {} stale", outcome.stale.len());
+    if !counts.is_empty() {
+        let by_reason: Vec<String> = counts
+            .iter()
+            .map(|&(reason, count)| format!("{count} {}", summary_label(reason)))
+            .collect();
+        line.push_str(&format!(" ({})", by_reason.join(", ")));
+    }
+    line.push_str(&format!(" / {} checked\n", outcome.checked.len()));
+    line
+}
+
+fn summary_label(reason: StaleReason) -> &'static str {
+    match reason {
+        StaleReason::EmptyDescription => "empty",
+        StaleReason::ContentChanged => "drift",
+        StaleReason::MissingBody => "missing body",
+        StaleReason::BannedPhrase => "banned phrase",
+        StaleReason::RestatesFilename => "restates filename",
+        StaleReason::SubjectTooThin => "thin subject",
+        StaleReason::DescriptionMismatchesDiff => "mismatched diff",
+        StaleReason::PatternMismatch => "pattern mismatch",
+        StaleReason::ReferencesMissingFile => "missing file",
+        StaleReason::NonConventionalFormat => "not conventional",
+        StaleReason::DescriptionTooShort => "too short",
+        StaleReason::PlaceholderDescription => "placeholder",
+    }
+}
+
+/// The union of every stale change's changed files, one per line.
+fn plain_files(stale: &[StalenessInfo]) -> String {
+    let files: BTreeSet<&str> = stale
+        .iter()
+        .flat_map(|info| &info.changed_files)
+        .map(|file| file.as_internal_file_string())
+        .collect();
+    files.into_iter().map(|file| format!("{file}\n")).collect()
+}
+
+/// One `::warning` annotation per stale change, anchored at its first
+/// changed file so the warning shows inline in the PR diff.
+fn github_annotations(stale: &[StalenessInfo], messages: &Messages) -> String {
+    let mut out = String::new();
+    for info in stale {
+        out.push_str("::warning ");
+        if let Some(file) = info.changed_files.first() {
+            out.push_str("file=");
+            out.push_str(&escape_property(file.as_internal_file_string()));
+            out.push(',');
+        }
+        out.push_str("title=Stale description::");
+        out.push_str(&escape_data(&crate::format_stale_change(info, messages)));
+        out.push('\n');
+    }
+    out
+}
+
+/// A JUnit test suite: checked changes pass, stale ones fail with the reason
+/// as the failure message and the full report as its body.
+fn junit(outcome: &Outcome<'_>) -> String {
+    let stale: HashMap<&str, &StalenessInfo> = outcome
+        .stale
+        .iter()
+        .map(|info| (info.change_id_short.as_str(), info))
+        .collect();
+
+    let mut cases = String::new();
+    let mut failures = 0;
+    for change_id in outcome.checked {
+        let name = escape_xml(change_id);
+        match stale.get(change_id.as_str()) {
+            Some(info) => {
+                failures += 1;
+                let reason = serde_json::json!(info.reason);
+                cases.push_str(&format!(
+                    "    <testcase classname=\"active-descriptions\" name=\"{name}\">\n      \
+                     <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                    escape_xml(reason.as_str().unwrap_or_default()),
+                    escape_xml(&crate::format_stale_change(info, outcome.messages)),
+                ));
+            }
+            None => cases.push_str(&format!(
+                "    <testcase classname=\"active-descriptions\" name=\"{name}\"/>\n"
+            )),
+        }
+    }
+
+    let tests = outcome.checked.len();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites tests=\"{tests}\" failures=\"{failures}\">\n  \
+         <testsuite name=\"active-descriptions\" tests=\"{tests}\" failures=\"{failures}\">\n\
+         {cases}  </testsuite>\n</testsuites>\n"
+    )
+}
+
+/// Escapes text for XML content and attribute values.
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Escapes a workflow command message per GitHub's rules.
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a workflow command property value, which additionally can't
+/// contain the `:` and `,` delimiters.
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use jj_lib::repo_path::RepoPathBuf;
+
+    use super::*;
+
+    fn info(change_id: &str, files: &[&str]) -> StalenessInfo {
+        StalenessInfo {
+            change_id_short: change_id.to_owned(),
+            reason: StaleReason::ContentChanged,
+            changed_files: files
+                .iter()
+                .map(|f| RepoPathBuf::from_internal_string(*f).expect("valid path"))
+                .collect(),
+            confidence: Confidence::High,
+            described_commit_id: None,
+            description: None,
+            detail: None,
+            line_counts: BTreeMap::new(),
+        }
+    }
+
+    fn outcome(stale: &[StalenessInfo]) -> Outcome<'_> {
+        Outcome {
+            stale,
+            ..Outcome::default()
+        }
+    }
+
+    #[test]
+    fn github_annotation_anchors_first_file() {
+        let stale = [info("kxqpmnrlzwzv", &["src/a,b.rs", "src/c.rs"])];
+        let out = render(OutputFormat::GithubAnnotations, &outcome(&stale));
+        assert_eq!(
+            out,
+            "::warning file=src/a%2Cb.rs,title=Stale description::\
+             Stale description: change kxqpmnrlzwzv modified since last described.\
+             %0A  Changed: src/a,b.rs, src/c.rs\n"
+        );
+    }
+
+    #[test]
+    fn github_annotation_escapes_percent() {
+        assert_eq!(escape_data("100%\r\n"), "100%25%0D%0A");
+        assert_eq!(escape_property("a:b,c"), "a%3Ab%2Cc");
+    }
+
+    #[test]
+    fn json_report_carries_confidence() {
+        let mut low = info("zzzzzzzzzzzz", &["b.txt"]);
+        low.confidence = Confidence::Low;
+        let stale = [info("kxqpmnrlzwzv", &["a.txt"]), low];
+        let out = render(OutputFormat::Json, &outcome(&stale));
+        let json: serde_json::Value = serde_json::from_str(&out).expect("valid json");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "staleChanges": [
+                    {
+                        "changeId": "kxqpmnrlzwzv",
+                        "reason": "contentChanged",
+                        "changedFiles": ["a.txt"],
+                        "confidence": "high",
+                    },
+                    {
+                        "changeId": "zzzzzzzzzzzz",
+                        "reason": "contentChanged",
+                        "changedFiles": ["b.txt"],
+                        "confidence": "low",
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn json_report_carries_described_commit_id() {
+        let mut described = info("kxqpmnrlzwzv", &["a.txt"]);
+        described.described_commit_id = Some(jj_lib::backend::CommitId::new(vec![0xab, 0xcd]));
+        let out = render(OutputFormat::Json, &outcome(&[described]));
+        let json: serde_json::Value = serde_json::from_str(&out).expect("valid json");
+        assert_eq!(json["staleChanges"][0]["describedCommitId"], "abcd");
+    }
+
+    #[test]
+    fn json_report_marks_truncation() {
+        let json = |truncated| -> serde_json::Value {
+            let outcome = Outcome {
+                truncated,
+                ..Outcome::default()
+            };
+            serde_json::from_str(&render(OutputFormat::Json, &outcome)).expect("valid json")
+        };
+        assert_eq!(json(true)["truncated"], true);
+        assert_eq!(json(false).get("truncated"), None);
+    }
+
+    #[test]
+    fn junit_is_well_formed_with_pass_fail_counts() {
+        let stale = [info("zzzz<&>zzzz", &["a.txt"])];
+        let checked = ["kxqpmnrlzwzv".to_owned(), "zzzz<&>zzzz".to_owned()];
+        let xml = render(
+            OutputFormat::Junit,
+            &Outcome {
+                stale: &stale,
+                checked: &checked,
+                ..Outcome::default()
+            },
+        );
+
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let (mut testcases, mut failures) = (0, 0);
+        loop {
+            match reader.read_event().expect("well-formed xml") {
+                quick_xml::events::Event::Start(e) | quick_xml::events::Event::Empty(e) => {
+                    match e.name().as_ref() {
+                        b"testcase" => testcases += 1,
+                        b"failure" => failures += 1,
+                        _ => {}
+                    }
+                }
+                quick_xml::events::Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!((testcases, failures), (2, 1));
+        assert!(xml.contains(r#"<testsuites tests="2" failures="1">"#));
+        assert!(xml.contains(r#"<failure message="contentChanged">"#));
+    }
+
+    #[test]
+    fn plain_files_dedups_and_sorts() {
+        let stale = [
+            info("kxqpmnrlzwzv", &["src/b.rs", "src/a.rs"]),
+            info("zzzzzzzzzzzz", &["src/a.rs", "README.md"]),
+        ];
+        assert_eq!(
+            render(OutputFormat::PlainFiles, &outcome(&stale)),
+            "README.md\nsrc/a.rs\nsrc/b.rs\n"
+        );
+    }
+
+    #[test]
+    fn summary_is_one_line_of_counts() {
+        let mut empty = info("zzzzzzzzzzzz", &[]);
+        empty.reason = StaleReason::EmptyDescription;
+        let stale = [info("kxqpmnrlzwzv", &["a.txt"]), empty];
+        let checked: Vec<String> = (0..7).map(|i| format!("change{i}")).collect();
+        let outcome = Outcome {
+            stale: &stale,
+            checked: &checked,
+            ..Outcome::default()
+        };
+        assert_eq!(
+            render(OutputFormat::Summary, &outcome),
+            "active-descriptions: 2 stale (1 empty, 1 drift) / 7 checked\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Summary, &Outcome::default()),
+            "active-descriptions: 0 stale / 0 checked\n"
+        );
+    }
+
+    #[test]
+    fn json_report_carries_stack_position() {
+        let stale = [info("kxqpmnrlzwzv", &["a.txt"]), info("zzzzzzzzzzzz", &[])];
+        let stack = [(
+            "kxqpmnrlzwzv".to_owned(),
+            StackPosition {
+                position: 2,
+                size: 3,
+            },
+        )];
+        let outcome = Outcome {
+            stale: &stale,
+            stack: &stack,
+            ..Outcome::default()
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&render(OutputFormat::Json, &outcome)).expect("valid json");
+        assert_eq!(json["staleChanges"][0]["stackPosition"], 2);
+        assert_eq!(json["staleChanges"][0]["stackSize"], 3);
+        assert_eq!(json["staleChanges"][1].get("stackPosition"), None);
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        let mut described = info("kxqpmnrlzwzv", &["a.txt", "src/b.rs"]);
+        described.described_commit_id = Some(jj_lib::backend::CommitId::new(vec![0xab, 0xcd]));
+        let mut empty = info("zzzzzzzzzzzz", &[]);
+        empty.reason = StaleReason::EmptyDescription;
+        let stale = [described, empty];
+        let checked: Vec<String> = (0..3).map(|i| format!("change{i}")).collect();
+        let outcome = Outcome {
+            stale: &stale,
+            checked: &checked,
+            truncated: true,
+            ..Outcome::default()
+        };
+
+        let out = render(OutputFormat::Toml, &outcome);
+        let parsed: Results = toml::from_str(&out).expect("valid toml");
+        assert_eq!(parsed, Results::new(&outcome));
+        assert_eq!(
+            parsed.summary,
+            Summary {
+                stale: 2,
+                checked: 3
+            }
+        );
+        assert_eq!(
+            parsed.report.stale_changes[0].changed_files,
+            ["a.txt", "src/b.rs"]
+        );
+    }
+
+    #[test]
+    fn count_is_just_the_stale_count() {
+        let stale = [info("kxqpmnrlzwzv", &["a.txt"]), info("zzzzzzzzzzzz", &[])];
+        assert_eq!(render(OutputFormat::Count, &outcome(&stale)), "2\n");
+        assert_eq!(render(OutputFormat::Count, &Outcome::default()), "0\n");
+    }
+}